@@ -2,6 +2,7 @@
 {
   inputs,
   cfg,
+  env,
   projectCfg,
   lockFile,
   local ? null,
@@ -15,16 +16,30 @@
     else tree // { rootDirectory = ""; } ;
 
   # TODO: Editable Projects MUST have a flake.lock
-  lock = builtins.fromJSON (builtins.readFile lockFile);
-  projectNames = lib.attrNames projectCfg;
+  # environments created with `ns env add` may not have a lockfile yet
+  lock =
+    if (builtins.pathExists lockFile) then builtins.fromJSON (builtins.readFile lockFile)
+    else { nodes = { root = { inputs = {}; }; }; root = "root"; version = 7; };
+  # projects may opt in or out of specific environments
+  inEnv = project:
+    (!(project ? environments) || builtins.elem env project.environments)
+    && !(project ? exclude_environments && builtins.elem env project.exclude_environments);
+  # editable projects may link a git worktree instead of their main checkout
+  checkoutPath = name: let
+    localCfg = local.projects.${name};
+  in
+    if localCfg ? worktree then localCfg.worktrees.${localCfg.worktree}
+    else projectCfg.${name}.path;
+  projectNames = lib.attrNames (lib.filterAttrs (name: inEnv) projectCfg);
   lockNodes = lib.filterAttrs (name: node: name != "root") lock.nodes;
 
   projects = builtins.mapAttrs (name: inputSpec:
     if (local != null && (builtins.hasAttr name local.projects) && local.projects.${name}.editable)
-    then fetchFlake {
+    # projects in a monorepo share one checkout; `dir` selects their flake
+    then fetchFlake ({
       type = "path";
-      path = impureRoot + "/" + projectCfg.${name}.path;
-    }
+      path = impureRoot + "/" + (checkoutPath name);
+    } // lib.optionalAttrs (inputSpec.locked ? dir) { inherit (inputSpec.locked) dir; })
     else fetchFlake inputSpec.locked
   ) lockNodes;
 
@@ -33,10 +48,11 @@
     // (
       builtins.mapAttrs (name: tree: let
         rootSrc = tree.outPath;
-        projLock = rootSrc + "/flake.lock";
+        flakeDir = if tree.rootDirectory == "" then rootSrc else "${rootSrc}/${tree.rootDirectory}";
+        projLock = flakeDir + "/flake.lock";
         lockFileStr =
           if (builtins.pathExists projLock)
-          then builtins.readFile (rootSrc + "/flake.lock")
+          then builtins.readFile projLock
           else ''{"nodes": {"root": {}}, "root": "root", "version": 7}''
         ;
       in
@@ -32,7 +32,7 @@
   envs = builtins.listToAttrs (map (env: {
     name = env;
     value = mkWorkspaceEnv {
-      inherit inputs cfg projectCfg local impureRoot;
+      inherit inputs cfg env projectCfg local impureRoot;
       lockFile = src + "/.nixspace/${env}.lock";
     };
   }) envNames);
@@ -0,0 +1,217 @@
+use anyhow::Result;
+use regex::{Captures, Regex};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use super::cli::Git;
+use super::lockfile::{InputSpec, LockFile};
+
+/// upstream commits listed per project; the rest are summarized
+const MAX_SUBJECTS: usize = 20;
+
+/// Default template for commit messages of lockfile updates.
+pub static DEFAULT_TEMPLATE: &str = "{summary}\n\n{changes}";
+
+/// A project whose locked revision differs between two lockfiles.
+#[derive(Debug)]
+pub struct ProjectChange {
+    pub name: String,
+    /// locked revision before the update, or `None` if the project is new
+    pub old: Option<String>,
+    /// locked revision after the update, or `None` if it was removed
+    pub new: Option<String>,
+    pub old_tag: Option<String>,
+    pub new_tag: Option<String>,
+    /// subjects of the upstream commits between the two revisions, newest
+    /// first
+    pub subjects: Vec<String>,
+}
+
+/// The changes between two lockfiles of an environment.
+#[derive(Debug)]
+pub struct Changelog {
+    pub env: String,
+    pub changes: Vec<ProjectChange>,
+}
+
+/// Identifies what a project is locked at: its revision, or the hash of its
+/// contents for inputs without revisions such as tarballs.
+fn locked_id(spec: InputSpec) -> Option<String> {
+    spec.rev.or(spec.nar_hash)
+}
+
+fn short(id: &str) -> &str {
+    match id.strip_prefix("sha256-") {
+        Some(hash) => &id[..id.len() - hash.len() + hash.len().min(12)],
+        None => &id[..id.len().min(7)],
+    }
+}
+
+impl Changelog {
+    /// Compares the projects locked by the root inputs of two lockfiles.
+    pub fn diff(env: &str, old: &LockFile, new: &LockFile) -> Result<Changelog> {
+        let locked = |lock: &LockFile| -> Result<BTreeMap<String, Option<String>>> {
+            Ok(lock.root_inputs()?.into_iter()
+                .map(|(name, node)| (name, lock.get_input_spec(&node).and_then(locked_id)))
+                .collect())
+        };
+        let (old, new) = (locked(old)?, locked(new)?);
+        let mut names: Vec<&String> = old.keys().chain(new.keys()).collect();
+        names.sort();
+        names.dedup();
+        let changes = names.into_iter()
+            .map(|name| (name, old.get(name).cloned().flatten(), new.get(name).cloned().flatten()))
+            .filter(|(_, old, new)| old != new)
+            .map(|(name, old, new)| ProjectChange {
+                name: name.to_string(),
+                old,
+                new,
+                old_tag: None,
+                new_tag: None,
+                subjects: Vec::new(),
+            })
+            .collect();
+        Ok(Changelog { env: env.to_string(), changes })
+    }
+
+    /// Looks up the tags and upstream commits of each changed project in a
+    /// cached mirror of its git remote. `remote` maps project names to their
+    /// remote URL. Projects whose history can't be read are left as-is.
+    pub fn fetch_commits(&mut self, remote: impl Fn(&str) -> Option<String>) {
+        let mut mirrors: BTreeMap<String, PathBuf> = BTreeMap::new();
+        for change in self.changes.iter_mut() {
+            let url = match remote(&change.name) {
+                Some(url) => url,
+                None => continue,
+            };
+            let mirror = match mirrors.get(&url) {
+                Some(m) => m.clone(),
+                None => match Git::mirror(&url) {
+                    Ok(m) => {
+                        mirrors.insert(url.to_string(), m.clone());
+                        m
+                    },
+                    Err(e) => {
+                        log::warn!("could not fetch the history of '{}': {e}", change.name);
+                        continue;
+                    },
+                },
+            };
+            change.old_tag = change.old.as_ref().and_then(|rev| Git::tags_at(rev, &mirror).ok()?.into_iter().next());
+            change.new_tag = change.new.as_ref().and_then(|rev| Git::tags_at(rev, &mirror).ok()?.into_iter().next());
+            if let (Some(old), Some(new)) = (&change.old, &change.new) {
+                match Git::log_subjects(&format!("{old}..{new}"), &mirror) {
+                    Ok(subjects) => change.subjects = subjects,
+                    Err(e) => log::warn!("could not list the commits of '{}': {e}", change.name),
+                }
+            }
+        }
+    }
+
+    /// Summarizes the update in one line, e.g. `update 3 projects in prod`.
+    pub fn summary(&self) -> String {
+        match self.changes.as_slice() {
+            [change] => format!("update {} in {}", change.name, self.env),
+            changes => format!("update {} projects in {}", changes.len(), self.env),
+        }
+    }
+
+    /// Lists each changed project with its old and new revision, followed
+    /// by the upstream commits between them.
+    pub fn body(&self) -> String {
+        let describe = |rev: &str, tag: &Option<String>| match tag {
+            Some(tag) => format!("{} ({tag})", short(rev)),
+            None => short(rev).to_string(),
+        };
+        let mut lines = Vec::new();
+        for change in &self.changes {
+            lines.push(match (&change.old, &change.new) {
+                (Some(old), Some(new)) => format!(
+                    "* {}: {} → {}", change.name, describe(old, &change.old_tag), describe(new, &change.new_tag),
+                ),
+                (None, Some(new)) => format!("* {}: added at {}", change.name, describe(new, &change.new_tag)),
+                (Some(old), None) => format!("* {}: removed (was {})", change.name, describe(old, &change.old_tag)),
+                (None, None) => unreachable!("unchanged projects are not part of the changelog"),
+            });
+            lines.extend(change.subjects.iter().take(MAX_SUBJECTS).map(|s| format!("  - {s}")));
+            if change.subjects.len() > MAX_SUBJECTS {
+                lines.push(format!("  - ... and {} more", change.subjects.len() - MAX_SUBJECTS));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Renders a message from a template. `{summary}`, `{changes}`, `{env}`,
+    /// `{count}` and `{projects}` are replaced; other text is kept as-is.
+    pub fn render(&self, template: &str) -> String {
+        let placeholder = Regex::new(r"\{(\w+)\}").unwrap();
+        let rendered = placeholder.replace_all(template, |caps: &Captures| match &caps[1] {
+            "summary" => self.summary(),
+            "changes" => self.body(),
+            "env" => self.env.to_string(),
+            "count" => self.changes.len().to_string(),
+            "projects" => self.changes.iter().map(|c| c.name.as_str()).collect::<Vec<_>>().join(", "),
+            _ => caps[0].to_string(),
+        });
+        format!("{}\n", rendered.trim_end())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lock(nodes: &[(&str, &str, Option<&str>)]) -> LockFile {
+        let inputs: Vec<String> = nodes.iter().map(|(name, _, _)| format!(r#""{name}": "{name}""#)).collect();
+        let locked: Vec<String> = nodes.iter().map(|(name, rev, nar_hash)| {
+            let hash = nar_hash.map(|h| format!(r#", "narHash": "{h}""#)).unwrap_or_default();
+            format!(r#""{name}": {{
+                "locked": {{ "type": "github", "owner": "chadac", "repo": "{name}", "rev": "{rev}"{hash} }},
+                "original": {{ "type": "github", "owner": "chadac", "repo": "{name}" }}
+            }}"#)
+        }).collect();
+        serde_json::from_str(&format!(
+            r#"{{ "nodes": {{ "root": {{ "inputs": {{ {} }} }}, {} }}, "root": "root", "version": 7 }}"#,
+            inputs.join(", "), locked.join(", "),
+        )).unwrap()
+    }
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let a1 = "a1a1a1a1a1a1a1a1a1a1a1a1a1a1a1a1a1a1a1a1";
+        let a2 = "a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2a2";
+        let b1 = "b1b1b1b1b1b1b1b1b1b1b1b1b1b1b1b1b1b1b1b1";
+        let c1 = "c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1";
+        let old = lock(&[("a", a1, None), ("b", b1, None), ("c", c1, None)]);
+        let new = lock(&[("a", a2, None), ("b", b1, None), ("d", c1, None)]);
+        let mut changelog = Changelog::diff("prod", &old, &new)?;
+        assert_eq!(changelog.changes.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["a", "c", "d"]);
+        changelog.changes[0].new_tag = Some("v1.1.0".to_string());
+        changelog.changes[0].subjects = (0..22).map(|i| format!("change {i}")).collect();
+
+        let message = changelog.render(DEFAULT_TEMPLATE);
+        let lines: Vec<&str> = message.lines().collect();
+        assert_eq!(lines[..4], [
+            "update 3 projects in prod",
+            "",
+            "* a: a1a1a1a → a2a2a2a (v1.1.0)",
+            "  - change 0",
+        ]);
+        assert_eq!(lines[lines.len() - 4..], [
+            "  - change 19",
+            "  - ... and 2 more",
+            "* c: removed (was c1c1c1c)",
+            "* d: added at c1c1c1c",
+        ]);
+        assert_eq!(
+            changelog.render("chore({env}): bump {projects} {unknown}"),
+            "chore(prod): bump a, c, d {unknown}\n",
+        );
+
+        let old = lock(&[("a", a1, Some("sha256-AAAAAAAAAAAAAAAAAAAAAAAA"))]);
+        let new = lock(&[("a", a1, None)]);
+        let changelog = Changelog::diff("dev", &old, &new)?;
+        assert!(changelog.changes.is_empty());
+        Ok(())
+    }
+}
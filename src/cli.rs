@@ -1,4 +1,6 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::process::{Command, Stdio, Output, ExitStatus};
 use serde::{Serialize, Deserialize};
 use anyhow::{anyhow, bail, Context, Result};
@@ -87,6 +89,7 @@ pub trait CliCommand {
         cwd: &P
     ) -> Result<ExitStatus> {
         let output = Command::new(Self::cmd())
+            .current_dir(cwd)
             .args(args)
             .output()?;
         Ok(output.status)
@@ -201,12 +204,22 @@ pub struct GitRef {
     pub git_ref: String,
 }
 
+#[derive(Debug, PartialEq)]
+pub struct GitStatus {
+    pub changed: usize,
+    pub untracked: usize,
+}
+
 impl CliCommand for Git {
     fn cmd() -> &'static str { "git" }
 }
 
 fn get_git_context<P: AsRef<Path> + ?Sized>(path: &P) -> Result<(PathBuf, String)> {
-    let path_abs = std::fs::canonicalize(&path)?;
+    // deleted files can't be canonicalized, so resolve through their parent
+    let path_abs = match (path.as_ref().exists(), path.as_ref().parent(), path.as_ref().file_name()) {
+        (false, Some(parent), Some(name)) => std::fs::canonicalize(parent)?.join(name),
+        _ => std::fs::canonicalize(path)?,
+    };
     let git_root = crate::util::find_root(".git", &path_abs)
         .with_context(|| anyhow!("could not find .git folder in any parent directory"))?;
 
@@ -225,26 +238,289 @@ impl Git {
         Self::exec(&["fetch"], cwd)
     }
 
-    pub fn push<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<CliOutput> {
-        Self::exec(&["push", "origin"], cwd)
+    pub fn fetch_remote<P: AsRef<Path> + ?Sized>(remote: &str, cwd: &P) -> Result<CliOutput> {
+        Self::exec(&["fetch", remote], cwd)
+    }
+
+    /// Pushes `refspec` to `remote`. With `force`, the remote branch is only
+    /// overwritten if it is still where it was when last fetched.
+    pub fn push<P: AsRef<Path> + ?Sized>(remote: &str, refspec: &str, force: bool, cwd: &P) -> Result<CliOutput> {
+        match force {
+            true => Self::exec(&["push", "--force-with-lease", remote, refspec], cwd),
+            false => Self::exec(&["push", remote, refspec], cwd),
+        }
+    }
+
+    /// Deletes `branch` from `remote`.
+    pub fn push_delete<P: AsRef<Path> + ?Sized>(remote: &str, branch: &str, cwd: &P) -> Result<CliOutput> {
+        Self::exec(&["push", "--delete", remote, branch], cwd)
     }
 
     pub fn pull_rebase<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<CliOutput> {
         Self::exec(&["pull", "--rebase"], cwd)
     }
 
-    /// Returns true if the file at the given path has been changed.
-    pub fn changed<P: AsRef<Path> + ?Sized>(file_path: &P) -> Result<bool> {
-        let (cwd, filename) = get_git_context(file_path)?;
-        let s1 = Self::run(&["diff", "--exit-code", &filename], &cwd)?;
-        if s1.success() {
-            let s2 = Self::run(&["diff", "--staged", "--exit-code", &filename], &cwd)?;
-            Ok(!s2.success())
+    /// Spells a remote URL the way git expects it. Flakes write local
+    /// repositories as `file:/path`, which git would read as an ssh host.
+    fn url(remote_url: &str) -> String {
+        match remote_url.strip_prefix("file:") {
+            Some(path) if !path.starts_with("//") => format!("file://{path}"),
+            _ => remote_url.to_string(),
+        }
+    }
+
+    /// Mirrors a remote repository into a bare repository in the cache, or
+    /// fetches into the mirror if it already exists. Returns the path of
+    /// the mirror.
+    pub fn mirror(remote_url: &str) -> Result<PathBuf> {
+        let dest = crate::util::mirror_dir("git", remote_url)?;
+        if dest.join("HEAD").exists() {
+            Self::exec(&["fetch", "--quiet", "--prune", "--tags"], &dest)?;
         } else {
-            Ok(true)
+            let parent = dest.parent().context("mirror has no parent directory")?;
+            std::fs::create_dir_all(parent)?;
+            let path = dest.to_str().context("mirror path is not valid unicode")?;
+            Self::exec(&["clone", "--quiet", "--mirror", &Self::url(remote_url), path], parent)?;
+        }
+        Ok(dest)
+    }
+
+    /// Returns the subjects of the commits in a revision range such as
+    /// `a..b`, newest first.
+    pub fn log_subjects<P: AsRef<Path> + ?Sized>(range: &str, cwd: &P) -> Result<Vec<String>> {
+        let out = Self::exec(&["log", "--format=%s", range], cwd)?;
+        Ok(out.stdout.lines().map(|l| l.to_string()).collect())
+    }
+
+    /// Lists the tags pointing at `rev`, highest version first.
+    pub fn tags_at<P: AsRef<Path> + ?Sized>(rev: &str, cwd: &P) -> Result<Vec<String>> {
+        let out = Self::exec(&["tag", "--points-at", rev, "--sort=-v:refname"], cwd)?;
+        Ok(out.stdout.lines().map(|l| l.to_string()).collect())
+    }
+
+    /// Returns true if a commit (`verify-commit`) or an annotated tag
+    /// (`verify-tag`) carries a valid SSH signature by a key listed in
+    /// `allowed_signers`. GPG and X.509 signatures are rejected, since they
+    /// would be checked against the keyring of whoever runs the command.
+    pub fn verify_signature<P: AsRef<Path> + ?Sized>(command: &str, object: &str, allowed_signers: &Path, cwd: &P) -> bool {
+        let signers = format!("gpg.ssh.allowedSignersFile={}", allowed_signers.display());
+        Self::exec(&[
+            "-c", &signers,
+            "-c", "gpg.openpgp.program=false",
+            "-c", "gpg.x509.program=false",
+            command, object,
+        ], cwd).is_ok()
+    }
+
+    /// Lists the files matching `pathspecs` that differ from HEAD, including
+    /// untracked and deleted files. Paths are relative to the root of the
+    /// repository.
+    pub fn changed_files<P: AsRef<Path> + ?Sized>(pathspecs: &[String], cwd: &P) -> Result<Vec<String>> {
+        let mut args = vec!["status", "--porcelain", "-z", "--untracked-files=all", "--"];
+        args.extend(pathspecs.iter().map(|p| p.as_str()));
+        let out = Self::exec(&args, cwd)?;
+        // entries are `XY path`, unquoted and separated by NUL; renames and
+        // copies are followed by their source path, which is changed too
+        let mut entries = out.stdout.split('\0').filter(|e| !e.is_empty());
+        let mut files = Vec::new();
+        while let Some(entry) = entries.next() {
+            if entry.len() <= 3 {
+                continue;
+            }
+            files.push(entry[3..].to_string());
+            if entry[..2].contains(['R', 'C']) {
+                files.extend(entries.next().map(|p| p.to_string()));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Returns the root directory of the repository containing `cwd`.
+    pub fn toplevel<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<PathBuf> {
+        let out = Self::exec(&["rev-parse", "--show-toplevel"], cwd)?;
+        Ok(PathBuf::from(out.stdout.trim()))
+    }
+
+    /// Returns the path of `cwd` relative to the root of its repository.
+    pub fn prefix<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<String> {
+        let out = Self::exec(&["rev-parse", "--show-prefix"], cwd)?;
+        Ok(out.stdout.trim().trim_end_matches('/').to_string())
+    }
+
+    /// Returns the URL of the `origin` remote, or of the only remote if the
+    /// repository has no `origin`.
+    pub fn remote_url<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<String> {
+        let out = Self::exec(&["remote"], cwd)?;
+        let remotes: Vec<&str> = out.stdout.lines().collect();
+        let remote = match remotes.as_slice() {
+            [] => bail!("repository has no remotes configured"),
+            [r] => r,
+            rs => rs.iter().find(|r| **r == "origin")
+                .context("repository has multiple remotes and none are named 'origin'")?,
+        };
+        let url = Self::exec(&["remote", "get-url", remote], cwd)?;
+        Ok(url.stdout.trim().to_string())
+    }
+
+    /// Returns true if the repository has any uncommitted or untracked changes.
+    pub fn dirty<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<bool> {
+        let status = Self::exec(&["status", "--porcelain"], cwd)?;
+        Ok(!status.stdout.trim().is_empty())
+    }
+
+    /// Returns the name of the branch checked out in `cwd`.
+    pub fn current_branch<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<String> {
+        let out = Self::exec(&["rev-parse", "--abbrev-ref", "HEAD"], cwd)?;
+        Ok(out.stdout.trim().to_string())
+    }
+
+    /// Returns the commit that `git_ref` resolves to.
+    pub fn rev_parse<P: AsRef<Path> + ?Sized>(git_ref: &str, cwd: &P) -> Result<String> {
+        let out = Self::exec(&["rev-parse", "--verify", &format!("{git_ref}^{{commit}}")], cwd)?;
+        Ok(out.stdout.trim().to_string())
+    }
+
+    /// Counts the commits in a revision range such as `a..b`.
+    pub fn count_commits<P: AsRef<Path> + ?Sized>(range: &str, cwd: &P) -> Result<usize> {
+        let out = Self::exec(&["rev-list", "--count", range], cwd)?;
+        Ok(out.stdout.trim().parse()?)
+    }
+
+    /// Returns true if `git_ref` resolves to a commit in the repository.
+    pub fn ref_exists<P: AsRef<Path> + ?Sized>(git_ref: &str, cwd: &P) -> bool {
+        Self::exec(&["rev-parse", "--verify", "--quiet", &format!("{git_ref}^{{commit}}")], cwd).is_ok()
+    }
+
+    /// Checks out `branch` into a new worktree at `path`. The branch is
+    /// created from `start` if it doesn't exist locally or on `origin`.
+    pub fn worktree_add<P: AsRef<Path> + ?Sized>(path: &Path, branch: &str, start: Option<&str>, cwd: &P) -> Result<CliOutput> {
+        let path = path.to_str().context("worktree path is not valid unicode")?;
+        let local = Self::ref_exists(&format!("refs/heads/{branch}"), cwd);
+        let remote = Self::ref_exists(&format!("refs/remotes/origin/{branch}"), cwd);
+        match (local || remote, start) {
+            (true, _) => Self::exec(&["worktree", "add", path, branch], cwd),
+            (false, Some(start)) => Self::exec(&["worktree", "add", "-b", branch, path, start], cwd),
+            (false, None) => Self::exec(&["worktree", "add", "-b", branch, path], cwd),
+        }
+    }
+
+    /// Switches to `branch`, creating it from `start` if it doesn't exist
+    /// locally or on `origin`.
+    pub fn switch<P: AsRef<Path> + ?Sized>(branch: &str, start: Option<&str>, cwd: &P) -> Result<CliOutput> {
+        let local = Self::ref_exists(&format!("refs/heads/{branch}"), cwd);
+        let remote = Self::ref_exists(&format!("refs/remotes/origin/{branch}"), cwd);
+        match (local || remote, start) {
+            (true, _) => Self::exec(&["switch", branch], cwd),
+            (false, Some(start)) => Self::exec(&["switch", "-c", branch, start], cwd),
+            (false, None) => Self::exec(&["switch", "-c", branch], cwd),
+        }
+    }
+
+    /// Deletes a local branch, even if it isn't merged.
+    pub fn delete_branch<P: AsRef<Path> + ?Sized>(branch: &str, cwd: &P) -> Result<CliOutput> {
+        Self::exec(&["branch", "-D", branch], cwd)
+    }
+
+    /// Moves the current branch to `rev`, keeping the index and working
+    /// tree as they are.
+    pub fn reset_soft<P: AsRef<Path> + ?Sized>(rev: &str, cwd: &P) -> Result<CliOutput> {
+        Self::exec(&["reset", "--soft", rev], cwd)
+    }
+
+    pub fn worktree_remove<P: AsRef<Path> + ?Sized>(path: &Path, force: bool, cwd: &P) -> Result<CliOutput> {
+        let path = path.to_str().context("worktree path is not valid unicode")?;
+        match force {
+            true => Self::exec(&["worktree", "remove", "--force", path], cwd),
+            false => Self::exec(&["worktree", "remove", path], cwd),
+        }
+    }
+
+    /// Counts the changed and untracked files in a checkout.
+    pub fn status<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<GitStatus> {
+        let out = Self::exec(&["status", "--porcelain"], cwd)?;
+        let untracked = out.stdout.lines().filter(|l| l.starts_with("??")).count();
+        Ok(GitStatus {
+            changed: out.stdout.lines().count() - untracked,
+            untracked,
+        })
+    }
+
+    pub fn stash_count<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<usize> {
+        let out = Self::exec(&["stash", "list"], cwd)?;
+        Ok(out.stdout.lines().count())
+    }
+
+    /// Counts the commits on local branches that are not on any remote.
+    pub fn unpushed_count<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<usize> {
+        let out = Self::exec(&["rev-list", "--count", "--branches", "--not", "--remotes"], cwd)?;
+        Ok(out.stdout.trim().parse()?)
+    }
+
+    /// Returns the upstream branch tracked by the current branch, such as
+    /// `origin/main`.
+    pub fn upstream<P: AsRef<Path> + ?Sized>(cwd: &P) -> Option<String> {
+        let out = Self::exec(&["rev-parse", "--abbrev-ref", "--symbolic-full-name", "@{upstream}"], cwd).ok()?;
+        Some(out.stdout.trim().to_string()).filter(|u| !u.is_empty())
+    }
+
+    /// Pushes a branch to the branch of the same name on `remote` and tracks
+    /// it.
+    pub fn push_upstream<P: AsRef<Path> + ?Sized>(remote: &str, branch: &str, cwd: &P) -> Result<CliOutput> {
+        Self::exec(&["push", "--set-upstream", remote, &format!("{branch}:refs/heads/{branch}")], cwd)
+    }
+
+    /// Returns the remote and the remote branch tracked by a local branch,
+    /// such as `("origin", "refs/heads/main")`.
+    pub fn tracking<P: AsRef<Path> + ?Sized>(branch: &str, cwd: &P) -> Result<Option<(String, String)>> {
+        let field = |name: &str| -> Result<String> {
+            let format = format!("--format=%(upstream:{name})");
+            let out = Self::exec(&["for-each-ref", &format, &format!("refs/heads/{branch}")], cwd)?;
+            Ok(out.stdout.trim().to_string())
+        };
+        let (remote, remote_ref) = (field("remotename")?, field("remoteref")?);
+        match remote.is_empty() || remote_ref.is_empty() {
+            true => Ok(None),
+            false => Ok(Some((remote, remote_ref))),
         }
     }
 
+    /// Returns true if `rev` is on any remote-tracking branch.
+    pub fn on_remote<P: AsRef<Path> + ?Sized>(rev: &str, cwd: &P) -> Result<bool> {
+        let out = Self::exec(&["branch", "--remotes", "--contains", rev], cwd)?;
+        Ok(!out.stdout.trim().is_empty())
+    }
+
+    /// Lists the branches and tags that contain `rev`, such as
+    /// `refs/heads/main`.
+    pub fn refs_containing<P: AsRef<Path> + ?Sized>(rev: &str, cwd: &P) -> Result<Vec<String>> {
+        let out = Self::exec(&["for-each-ref", "--format=%(refname)", "--contains", rev, "refs/heads", "refs/tags"], cwd)?;
+        Ok(out.stdout.lines().map(|l| l.to_string()).collect())
+    }
+
+    /// Returns the branch that `HEAD` points at, such as `refs/heads/main`.
+    pub fn head_ref<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<String> {
+        let out = Self::exec(&["symbolic-ref", "HEAD"], cwd)?;
+        Ok(out.stdout.trim().to_string())
+    }
+
+    pub fn merge_ff_only<P: AsRef<Path> + ?Sized>(target: &str, cwd: &P) -> Result<CliOutput> {
+        Self::exec(&["merge", "--ff-only", target], cwd)
+    }
+
+    pub fn rebase<P: AsRef<Path> + ?Sized>(onto: &str, cwd: &P) -> Result<CliOutput> {
+        Self::exec(&["rebase", onto], cwd)
+    }
+
+    pub fn rebase_abort<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<CliOutput> {
+        Self::exec(&["rebase", "--abort"], cwd)
+    }
+
+    /// Lists the files with unresolved merge conflicts.
+    pub fn conflicts<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<Vec<String>> {
+        let out = Self::exec(&["diff", "--name-only", "--diff-filter=U"], cwd)?;
+        Ok(out.stdout.lines().map(|l| l.to_string()).collect())
+    }
+
     pub fn add<P: AsRef<Path> + ?Sized>(file_path: &P) -> Result<CliOutput> {
         let (cwd, filename) = get_git_context(file_path)?;
         Self::exec(&["add", "-f", &filename], &cwd)
@@ -259,13 +535,28 @@ impl Git {
         Self::exec(&["commit", "-m", message], cwd)
     }
 
-    pub fn reset<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<CliOutput> {
-        Self::exec(&["reset"], cwd)
+    /// Commits the current contents of `paths`, including deletions. Other
+    /// changes in the index stay staged and are not committed.
+    pub fn commit_paths<P: AsRef<Path> + ?Sized>(message: &str, paths: &[String], cwd: &P) -> Result<CliOutput> {
+        let paths: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+        Self::exec(&[&["add", "--all", "--"], &paths[..]].concat(), cwd)?;
+        Self::exec(&[&["commit", "-m", message, "--only", "--"], &paths[..]].concat(), cwd)
     }
+}
+
+/// A version control system whose remote branches and tags can be queried
+/// by update strategies.
+pub trait Vcs {
+    /// Lists the heads and tags of a remote repository using git's naming:
+    /// `HEAD`, `refs/heads/<branch>` and `refs/tags/<tag>`. Tags are sorted
+    /// by version.
+    fn ls_remote(remote_url: &str) -> Result<Vec<GitRef>>;
+}
 
-    pub fn ls_remote(remote_url: &str) -> Result<Vec<GitRef>> {
+impl Vcs for Git {
+    fn ls_remote(remote_url: &str) -> Result<Vec<GitRef>> {
         let result = Self::exec(
-            &["ls-remote", "--sort", "v:refname", remote_url],
+            &["ls-remote", "--sort", "v:refname", &Self::url(remote_url)],
             &std::env::current_dir()?
         )?;
         let raw = result.stdout.trim();
@@ -281,7 +572,83 @@ impl Git {
         }
         Ok(refs)
     }
+}
+
+/// Memoizes `ls_remote` so that projects sharing a repository only query
+/// the remote once.
+#[derive(Default)]
+pub struct RemoteRefs {
+    refs: BTreeMap<String, Rc<Vec<GitRef>>>,
+}
+
+impl RemoteRefs {
+    pub fn get<V: Vcs>(&mut self, remote_url: &str) -> Result<Rc<Vec<GitRef>>> {
+        let key = crate::flake::normalize_remote(remote_url);
+        if let Some(refs) = self.refs.get(&key) {
+            return Ok(refs.clone());
+        }
+        let refs = Rc::new(V::ls_remote(remote_url)?);
+        self.refs.insert(key, refs.clone());
+        Ok(refs)
+    }
+}
+
+/// Minimal wrapper around the Mercurial CLI
+pub struct Hg {}
 
+impl CliCommand for Hg {
+    fn cmd() -> &'static str { "hg" }
+}
+
+impl Hg {
+    /// Clones a remote repository into a local mirror without a working
+    /// copy, or pulls into the mirror if it already exists.
+    pub fn mirror(remote_url: &str, dest: &Path) -> Result<()> {
+        if dest.join(".hg").exists() {
+            Self::exec(&["pull", "--quiet", remote_url], dest)?;
+        } else {
+            let parent = dest.parent().context("mirror has no parent directory")?;
+            std::fs::create_dir_all(parent)?;
+            let dest = dest.to_str().context("mirror path is not valid unicode")?;
+            Self::exec(&["clone", "--quiet", "--noupdate", remote_url, dest], parent)?;
+        }
+        Ok(())
+    }
+
+    /// Lists `<name> <node>` pairs printed by one of `hg branches`,
+    /// `hg bookmarks` or `hg tags`.
+    fn list(subcommand: &str, template: &str, cwd: &Path) -> Result<Vec<(String, String)>> {
+        let out = Self::exec(&[subcommand, "--template", &format!("{{{template}}} {{node}}\n")], cwd)?;
+        out.stdout.lines()
+            .map(|line| line.rsplit_once(' ')
+                .map(|(name, node)| (name.to_string(), node.to_string()))
+                .with_context(|| anyhow!("hg {subcommand}: unexpected input '{line}'")))
+            .collect()
+    }
+}
+
+impl Vcs for Hg {
+    /// Mercurial can't list the heads of a remote directly, so the remote is
+    /// mirrored into the cache first. Branches and bookmarks are both listed
+    /// as heads, and `HEAD` is the tip of the `default` branch.
+    fn ls_remote(remote_url: &str) -> Result<Vec<GitRef>> {
+        let mirror = crate::util::mirror_dir("hg", remote_url)?;
+        Self::mirror(remote_url, &mirror)?;
+
+        let head = Self::exec(&["log", "--rev", "default", "--template", "{node}"], &mirror)?;
+        let mut refs = vec![GitRef { rev: head.stdout.trim().to_string(), git_ref: "HEAD".to_string() }];
+        let mut heads = Self::list("branches", "branch", &mirror)?;
+        heads.extend(Self::list("bookmarks", "bookmark", &mirror)?);
+        refs.extend(heads.into_iter()
+            .map(|(name, rev)| GitRef { rev, git_ref: format!("refs/heads/{name}") }));
+        let mut tags: Vec<_> = Self::list("tags", "tag", &mirror)?.into_iter()
+            .filter(|(name, _)| name != "tip")
+            .collect();
+        tags.sort_by(|(a, _), (b, _)| crate::util::compare_versions(a, b));
+        refs.extend(tags.into_iter()
+            .map(|(name, rev)| GitRef { rev, git_ref: format!("refs/tags/{name}") }));
+        Ok(refs)
+    }
 }
 
 #[cfg(test)]
@@ -322,3 +689,54 @@ mod git_tests {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod remote_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Counting {}
+
+    impl Vcs for Counting {
+        fn ls_remote(_remote_url: &str) -> Result<Vec<GitRef>> {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![GitRef { rev: "a3a3".to_string(), git_ref: "HEAD".to_string() }])
+        }
+    }
+
+    #[test]
+    fn test_remote_refs_are_shared() -> Result<()> {
+        let mut remotes = RemoteRefs::default();
+        remotes.get::<Counting>("https://github.com/chadac/mono.git")?;
+        remotes.get::<Counting>("git@github.com:chadac/mono")?;
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+        remotes.get::<Counting>("https://github.com/chadac/other.git")?;
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod hg_tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    #[ignore]
+    fn test_ls_remote() -> Result<()> {
+        let tmp = TempDir::new("hg")?;
+        let repo = tmp.path().join("repo");
+        std::env::set_var("XDG_CACHE_HOME", tmp.path().join("cache"));
+        Hg::exec(&["init", repo.to_str().unwrap()], tmp.path())?;
+        std::fs::write(repo.join("flake.nix"), "{ outputs = _: {}; }")?;
+        Hg::exec(&["commit", "--addremove", "-m", "init", "-u", "test"], &repo)?;
+        Hg::exec(&["tag", "v1.10.0", "-u", "test"], &repo)?;
+        Hg::exec(&["tag", "v1.9.0", "-u", "test"], &repo)?;
+        let refs = Hg::ls_remote(repo.to_str().unwrap())?;
+        let names: Vec<&str> = refs.iter().map(|r| r.git_ref.as_str()).collect();
+        assert_eq!(names, vec!["HEAD", "refs/heads/default", "refs/tags/v1.9.0", "refs/tags/v1.10.0"]);
+        Ok(())
+    }
+}
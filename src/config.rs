@@ -1,13 +1,15 @@
 use std::collections::BTreeMap;
 use serde::{Serialize, Deserialize};
-use anyhow::{anyhow, Context, Error, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use glob_match::glob_match;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use super::flake::FlakeRef;
-use super::lockfile::InputSpec;
-use super::cli::{CliCommand, Git, Nix};
+use super::lockfile::{FlakeType, InputSpec};
+use super::cli::{CliCommand, Git, GitRef, Hg, Nix, RemoteRefs};
+use super::forge::ForgeConfig;
+use super::policy::PolicyConfig;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
@@ -15,6 +17,101 @@ pub struct Config {
     pub projects: Vec<ProjectConfig>,
 
     pub default_env: String,
+    /// how `ns sync --local` updates editable checkouts
+    #[serde(default, skip_serializing_if = "SyncMode::is_default")]
+    pub sync: SyncMode,
+    /// where `ns update --branch` opens review requests
+    #[serde(default, skip_serializing_if = "ForgeConfig::is_default")]
+    pub forge: ForgeConfig,
+    /// which sources projects and their inputs may be fetched from
+    #[serde(default, skip_serializing_if = "PolicyConfig::is_default")]
+    pub policy: PolicyConfig,
+}
+
+/// How editable checkouts are brought up to date with their upstream
+/// branch.
+#[derive(clap::ValueEnum, Clone, Copy, Default, PartialEq, Serialize, Deserialize, Debug)]
+pub enum SyncMode {
+    /// only fast-forward; checkouts with local commits are reported as
+    /// conflicts
+    #[default]
+    #[serde(rename = "fast-forward")]
+    FastForward,
+    /// rebase local commits onto the upstream branch
+    #[serde(rename = "rebase")]
+    Rebase,
+}
+
+/// The signature that revisions picked by an update strategy must carry.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Debug)]
+pub enum Verification {
+    /// a signed annotated tag must point at the revision
+    #[serde(rename = "signed-tag")]
+    SignedTag,
+    /// the commit itself must be signed
+    #[serde(rename = "signed-commit")]
+    SignedCommit,
+}
+
+impl Verification {
+    /// Checks a commit in a mirror of its repository.
+    pub fn verified(&self, commit: &str, allowed_signers: &Path, mirror: &Path) -> bool {
+        match self {
+            Self::SignedCommit => Git::verify_signature("verify-commit", commit, allowed_signers, mirror),
+            Self::SignedTag => Git::tags_at(commit, mirror).unwrap_or_default().iter()
+                .any(|tag| Git::verify_signature("verify-tag", &format!("refs/tags/{tag}"), allowed_signers, mirror)),
+        }
+    }
+}
+
+/// Requires the revision picked by an update strategy to be signed by a
+/// trusted key.
+pub struct SignatureCheck {
+    pub verify: Verification,
+    /// SSH keys that are trusted to sign, in the format of git's
+    /// `gpg.ssh.allowedSignersFile`
+    pub allowed_signers: PathBuf,
+    /// revision to keep if none of the candidates are signed
+    pub locked: Option<String>,
+}
+
+impl SignatureCheck {
+    /// Returns the first candidate that is signed by a trusted key. Older
+    /// candidates than the locked revision are not considered; if no newer
+    /// candidate is signed, the locked revision is kept.
+    fn pick(&self, candidates: &[String], remote_url: &str) -> Result<Option<String>> {
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        // signatures can only be checked against the objects themselves
+        let mirror = Git::mirror(remote_url)?;
+        for candidate in candidates {
+            let commit = match Git::rev_parse(candidate, &mirror) {
+                Ok(commit) => commit,
+                Err(_) => continue,
+            };
+            if self.verify.verified(&commit, &self.allowed_signers, &mirror) {
+                return Ok(Some(commit));
+            }
+            log::debug!("skipping {commit} of {remote_url}; it is not signed by a trusted key");
+            if self.locked.as_ref() == Some(&commit) {
+                break;
+            }
+        }
+        match &self.locked {
+            Some(locked) => {
+                log::warn!("no new revision of {remote_url} is signed by a trusted key; keeping {locked}");
+                Ok(Some(locked.to_string()))
+            },
+            None => bail!("no revision of {remote_url} is signed by a trusted key, and none is locked to fall back to"),
+        }
+    }
+}
+
+impl SyncMode {
+    fn is_default(&self) -> bool {
+        *self == SyncMode::default()
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -33,6 +130,20 @@ pub enum UpdateStrategy {
 pub struct EnvConfig {
     pub name: String,
     pub strategy: UpdateStrategy,
+    /// signature that updated revisions must carry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<Verification>,
+    /// set for throwaway environments created by `ns env preview`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<PreviewConfig>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct PreviewConfig {
+    /// environment the preview was copied from
+    pub base: String,
+    /// when the preview was created, in seconds since the Unix epoch
+    pub created: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -40,69 +151,184 @@ pub struct ProjectConfig {
     pub name: String,
     pub url: String,
     pub path: Option<PathBuf>,
+    /// if present, the project is only included in these environments
+    pub environments: Option<Vec<String>>,
+    /// environments the project is excluded from
+    pub exclude_environments: Option<Vec<String>>,
+    /// tags used for addressing groups of projects, i.e. `ns edit @backend`
+    pub tags: Option<Vec<String>>,
     pub strategy: Option<BTreeMap<String, UpdateStrategy>>,
+    /// versions substituted into a tarball URL template such as
+    /// `https://example.com/foo-{version}.tar.gz`
+    pub versions: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LocalConfig {
-    pub projects: BTreeMap<String, LocalProjectConfig>
+    pub projects: BTreeMap<String, LocalProjectConfig>,
+    /// changes spanning several editable projects, by branch name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub changesets: BTreeMap<String, ChangesetConfig>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A branch of the same name in several editable projects, built and
+/// reviewed together.
+#[derive(Clone, Default, Serialize, Deserialize, Debug)]
+pub struct ChangesetConfig {
+    /// environment that the changeset's environment is based on
+    pub env: String,
+    /// the revision each project's branch was started at
+    pub projects: BTreeMap<String, String>,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug)]
 pub struct LocalProjectConfig {
     pub editable: bool,
+    /// git worktrees of the project's checkout, by branch
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub worktrees: BTreeMap<String, PathBuf>,
+    /// branch of the worktree linked to the workspace; if unset, the main
+    /// checkout is linked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worktree: Option<String>,
+    /// the locked revision the project was checked out at by `ns edit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<EditBase>,
+}
+
+/// Where an editable project's checkout started from.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct EditBase {
+    /// environment whose lockfile the revision was taken from
+    pub env: String,
+    /// locked revision
+    pub rev: String,
+    /// ref the lockfile follows, if any
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+    /// local branch that was checked out
+    pub branch: String,
+}
+
+impl std::str::FromStr for UpdateStrategy {
+    type Err = Error;
+
+    /// Parses a strategy from the CLI. Accepts either the JSON form used by
+    /// `ns env set` (e.g. `{"branch": "main"}`) or a bare strategy name such
+    /// as `latest`.
+    fn from_str(s: &str) -> Result<Self> {
+        serde_json::from_str(s)
+            .or_else(|_| serde_json::from_value(serde_json::Value::String(s.to_string())))
+            .with_context(|| anyhow!("could not parse update strategy '{s}'"))
+    }
 }
 
 impl UpdateStrategy {
-    pub fn update(&self, flake_ref: Rc<dyn FlakeRef>) -> Result<super::cli::FlakeMetadata> {
-        let mut new_ref = flake_ref.clone();
-        if let Some(remote_url) = flake_ref.git_remote_url() {
-            if let Some(rev) = self.get_git_rev(&remote_url)? {
-                new_ref = flake_ref.with_rev(&rev);
-            }
+    /// Resolves the flake reference a project should be locked to. Projects
+    /// with a tarball URL template pick one of `versions`.
+    ///
+    /// Remote queries go through `remotes`, so that they can be shared
+    /// between projects in the same repository.
+    pub fn update(&self, flake_ref: Rc<dyn FlakeRef>, versions: &Option<Vec<String>>, remotes: &mut RemoteRefs, signatures: Option<&SignatureCheck>) -> Result<super::cli::FlakeMetadata> {
+        if signatures.is_some() && flake_ref.git_remote_url().is_none() && !matches!(self, Self::Freeze) {
+            bail!("cannot verify signatures of '{}'; only git repositories can be verified", flake_ref.flake_url());
         }
+        let rev = if let Some(remote_url) = flake_ref.git_remote_url() {
+            let refs = remotes.get::<Git>(&remote_url)?;
+            match signatures {
+                Some(check) => check.pick(&self.candidates(refs.as_ref())?, &remote_url)?,
+                None => self.get_rev(refs.as_ref())?,
+            }
+        } else if let Some(remote_url) = flake_ref.hg_remote_url() {
+            self.get_rev(remotes.get::<Hg>(&remote_url)?.as_ref())?
+        } else if flake_ref.flake_type() == FlakeType::Tarball && crate::flake::is_template(&flake_ref.flake_url()) {
+            // frozen projects keep their locked version; one is only picked
+            // when nothing is locked yet
+            match self.get_version(versions)? {
+                Some(version) => Some(version),
+                None => Self::Latest.get_version(versions)?,
+            }
+        } else {
+            None
+        };
+        let new_ref = match rev {
+            Some(rev) => flake_ref.with_rev(&rev),
+            None => flake_ref.clone(),
+        };
         let metadata = Nix::flake_metadata(
             &new_ref.flake_url()
         )?;
         Ok(metadata)
     }
 
-    fn get_git_rev(&self, remote_url: &str) -> Result<Option<String>> {
+    fn get_rev(&self, revs: &[GitRef]) -> Result<Option<String>> {
+        Ok(self.candidates(revs)?.into_iter().next())
+    }
+
+    /// Lists the revisions the strategy can pick from, best first: the head
+    /// of the followed branch, or every matching tag from the highest
+    /// version down.
+    fn candidates(&self, revs: &[GitRef]) -> Result<Vec<String>> {
         match self {
             Self::Latest => {
-                let revs = Git::ls_remote(remote_url)?;
-                Ok(Some(revs.iter()
+                Ok(vec![revs.iter()
                     .find(|r| r.git_ref == "HEAD")
                     .ok_or(Error::msg("could not find HEAD in repository"))?
-                    .rev.clone()))
+                    .rev.clone()])
             },
             Self::Freeze => {
-                Ok(None)
+                Ok(Vec::new())
             },
             Self::LatestTag(pattern) => {
-                let revs = Git::ls_remote(remote_url)?;
                 let tag_pattern = match pattern {
                     Some(p) => p,
                     None => "*"
                 };
                 let glob = format!("refs/tags/{}", &tag_pattern);
                 Ok(revs.iter()
+                    .rev()
                     .filter(|r| glob_match(&glob, &r.git_ref))
                     .map(|r| r.rev.clone())
-                    .last())
+                    .collect())
             },
             Self::Branch(branch) => {
-                let revs = Git::ls_remote(remote_url)?;
-                let git_ref = format!("refs/branches/{}", branch);
-                Ok(Some(
+                let git_ref = format!("refs/heads/{}", branch);
+                Ok(vec![
                     revs.iter()
-                        .find(|r| r.git_ref == *branch)
+                        .find(|r| r.git_ref == git_ref)
                         .ok_or(Error::msg("could not find specified branch in repository"))?
                         .rev.clone()
-                ))
+                ])
             }
         }
     }
+
+    /// Returns true if a revision on `git_ref` could have been picked by this
+    /// strategy. `head` is the branch that the remote's `HEAD` points at.
+    pub fn follows(&self, git_ref: &str, head: &str) -> bool {
+        match self {
+            Self::Latest => git_ref == head,
+            Self::Freeze => true,
+            Self::LatestTag(pattern) => glob_match(&format!("refs/tags/{}", pattern.as_deref().unwrap_or("*")), git_ref),
+            Self::Branch(branch) => git_ref == format!("refs/heads/{branch}"),
+        }
+    }
+
+    /// Picks a version for a tarball URL template. Tags are matched against
+    /// the versions; branches have no meaning for tarballs. Returns `None`
+    /// for frozen projects, which keep the version they are locked at.
+    fn get_version(&self, versions: &Option<Vec<String>>) -> Result<Option<String>> {
+        let mut versions = versions.clone().unwrap_or_default();
+        versions.sort_by(|a, b| crate::util::compare_versions(a, b));
+        let version = match self {
+            Self::Freeze => return Ok(None),
+            Self::Latest => versions.last(),
+            Self::LatestTag(pattern) => versions.iter()
+                .rfind(|v| glob_match(pattern.as_deref().unwrap_or("*"), v)),
+            Self::Branch(_) => bail!("the branch strategy is not supported for tarball URL templates"),
+        };
+        version.cloned().map(Some).context("no versions match the update strategy; add them to the project's `versions`")
+    }
 }
 
 impl Config {
@@ -110,12 +336,17 @@ impl Config {
         let mut default_envs = Vec::new();
         default_envs.push(EnvConfig {
             name: "dev".to_string(),
-            strategy: UpdateStrategy::Latest
+            strategy: UpdateStrategy::Latest,
+            verify: None,
+            preview: None,
         });
         Config {
             environments: default_envs,
             projects: Vec::new(),
             default_env: "dev".to_string(),
+            sync: SyncMode::default(),
+            forge: ForgeConfig::default(),
+            policy: PolicyConfig::default(),
         }
     }
 
@@ -143,11 +374,95 @@ impl Config {
         self.environments.iter().map(|env| env.name.to_string()).collect()
     }
 
+    pub fn add_env(&mut self, name: &str, strategy: UpdateStrategy) -> Result<&EnvConfig> {
+        if self.env(name).is_ok() {
+            bail!("environment already exists: '{}'", name);
+        }
+        self.environments.push(EnvConfig {
+            name: name.to_string(),
+            strategy,
+            verify: None,
+            preview: None,
+        });
+        Ok(self.environments.last().unwrap())
+    }
+
+    /// Removes an environment along with any project-level strategy
+    /// overrides that reference it.
+    pub fn rm_env(&mut self, name: &str) -> Result<EnvConfig> {
+        if self.default_env == name {
+            bail!("cannot remove the default environment '{}'; change `default_env` first", name);
+        }
+        let index = self.environments.iter().position(|env| env.name == name)
+            .with_context(|| anyhow!("environment does not exist: '{}'", name))?;
+        for project in &mut self.projects {
+            if let Some(strategy) = &mut project.strategy {
+                strategy.remove(name);
+            }
+            for envs in [&mut project.environments, &mut project.exclude_environments].into_iter().flatten() {
+                envs.retain(|e| e != name);
+            }
+        }
+        Ok(self.environments.remove(index))
+    }
+
+    /// Renames an environment, updating `default_env` and any project-level
+    /// strategy overrides.
+    pub fn rename_env(&mut self, name: &str, new_name: &str) -> Result<()> {
+        if self.env(new_name).is_ok() {
+            bail!("environment already exists: '{}'", new_name);
+        }
+        self.env_mut(name)?.name = new_name.to_string();
+        if self.default_env == name {
+            self.default_env = new_name.to_string();
+        }
+        for project in &mut self.projects {
+            if let Some(strategy) = &mut project.strategy {
+                if let Some(s) = strategy.remove(name) {
+                    strategy.insert(new_name.to_string(), s);
+                }
+            }
+            for envs in [&mut project.environments, &mut project.exclude_environments].into_iter().flatten() {
+                for e in envs.iter_mut().filter(|e| *e == name) {
+                    *e = new_name.to_string();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies project-level strategy overrides and environment membership
+    /// from one environment to another.
+    pub fn copy_env_projects(&mut self, from: &str, to: &str) {
+        for project in &mut self.projects {
+            if let Some(strategy) = &mut project.strategy {
+                if let Some(s) = strategy.get(from).cloned() {
+                    strategy.insert(to.to_string(), s);
+                }
+            }
+            for envs in [&mut project.environments, &mut project.exclude_environments].into_iter().flatten() {
+                if envs.iter().any(|e| e == from) {
+                    envs.push(to.to_string());
+                }
+            }
+        }
+    }
+
     pub fn project(&self, name: &str) -> Result<&ProjectConfig> {
         self.projects.iter().find(|p| p.name == name)
             .with_context(|| anyhow!("could not find project '{}'", name))
     }
 
+    pub fn project_mut(&mut self, name: &str) -> Result<&mut ProjectConfig> {
+        self.projects.iter_mut().find(|p| p.name == name)
+            .with_context(|| anyhow!("could not find project '{}'", name))
+    }
+
+    /// Returns all projects that are members of an environment.
+    pub fn projects_in_env(&self, env: &str) -> Vec<&ProjectConfig> {
+        self.projects.iter().filter(|p| p.in_env(env)).collect()
+    }
+
     pub fn add_project<P: AsRef<Path>>(
         &mut self,
         name: &str,
@@ -159,29 +474,48 @@ impl Config {
         //         anyhow!("could not infer a good project name to use.")
         //     )?
         // );
+        if self.project(name).is_ok() {
+            bail!("project '{name}' is already registered");
+        }
+        if let Some(existing) = self.get_project_by_flake_ref(flake_ref) {
+            bail!("'{}' is already registered as project '{}'", flake_ref.flake_url(), existing.name);
+        }
         let pb = match path {
-            Some(p) => Some(PathBuf::from(p.as_ref())),
+            Some(p) => Some(crate::util::normalize_relative_path(p)
+                .with_context(|| anyhow!("invalid path for project '{name}'"))?),
             None => None
         };
         self.projects.push(ProjectConfig {
             name: name.to_string(),
             url: flake_ref.flake_url(),
             path: pb,
+            environments: None,
+            exclude_environments: None,
+            tags: None,
             strategy: None,
+            versions: None,
         });
         Ok(self.projects.last().unwrap())
     }
 
     pub fn rm_project(&mut self, flake_ref: &dyn FlakeRef) -> Result<ProjectConfig> {
-        let index = self.projects.iter().position(|x| x.url == flake_ref.flake_url()).ok_or(
+        let index = self.position_of(flake_ref).ok_or(
             anyhow!("project with ref '{}' not found", flake_ref.flake_url())
         )?;
         Ok(self.projects.remove(index))
     }
 
-    pub fn get_project_by_flake_ref(&self, flake_ref: Rc<dyn FlakeRef>) -> Option<&ProjectConfig> {
-        let url = flake_ref.flake_url();
-        self.projects.iter().find(|p| p.url == url)
+    /// Finds the project registered under a reference equivalent to
+    /// `flake_ref`, however either of them is spelled.
+    pub fn get_project_by_flake_ref(&self, flake_ref: &dyn FlakeRef) -> Option<&ProjectConfig> {
+        self.position_of(flake_ref).map(|i| &self.projects[i])
+    }
+
+    fn position_of(&self, flake_ref: &dyn FlakeRef) -> Option<usize> {
+        self.projects.iter().position(|p| match p.flake_ref() {
+            Ok(f) => f.equivalent(flake_ref),
+            Err(_) => p.url == flake_ref.flake_url(),
+        })
     }
 }
 
@@ -189,6 +523,7 @@ impl LocalConfig {
     pub fn new() -> Self {
         LocalConfig {
             projects: BTreeMap::new(),
+            changesets: BTreeMap::new(),
         }
     }
 
@@ -208,11 +543,20 @@ impl LocalConfig {
     }
 
     pub fn mark_editable(&mut self, project_name: &str) -> () {
-        self.projects.insert(project_name.to_string(), LocalProjectConfig { editable: true });
+        self.projects.entry(project_name.to_string()).or_default().editable = true;
     }
 
     pub fn unmark_editable(&mut self, project_name: &str) -> () {
-        self.projects.insert(project_name.to_string(), LocalProjectConfig { editable: false });
+        let project = self.projects.entry(project_name.to_string()).or_default();
+        project.editable = false;
+        project.worktree = None;
+        project.base = None;
+    }
+
+    /// Returns the path of the worktree linked to the workspace, if any.
+    pub fn active_worktree(&self, project_name: &str) -> Option<&PathBuf> {
+        let project = self.projects.get(project_name)?;
+        project.worktrees.get(project.worktree.as_ref()?)
     }
 }
 
@@ -220,6 +564,17 @@ impl ProjectConfig {
     pub fn flake_ref(&self) -> Result<Rc<dyn FlakeRef>> {
         crate::flake::parse(&self.url)
     }
+
+    /// Returns true if the project is a member of the given environment.
+    pub fn in_env(&self, env: &str) -> bool {
+        let included = self.environments.as_ref()
+            .map(|envs| envs.iter().any(|e| e == env))
+            .unwrap_or(true);
+        let excluded = self.exclude_environments.as_ref()
+            .map(|envs| envs.iter().any(|e| e == env))
+            .unwrap_or(false);
+        included && !excluded
+    }
 }
 
 #[cfg(test)]
@@ -230,11 +585,19 @@ mod tests {
     fn test_deserialize() {
         let config = Config {
             environments: Vec::from([
-                EnvConfig { name: "dev".to_string(), strategy: UpdateStrategy::Latest, },
-                EnvConfig { name: "stage".to_string(), strategy: UpdateStrategy::Freeze, },
+                EnvConfig { name: "dev".to_string(), strategy: UpdateStrategy::Latest, verify: None, preview: None, },
+                EnvConfig { name: "stage".to_string(), strategy: UpdateStrategy::Freeze, verify: None, preview: None, },
                 EnvConfig {
                     name: "prod".to_string(),
                     strategy: UpdateStrategy::LatestTag(Some("release-*".to_string())),
+                    verify: Some(Verification::SignedTag),
+                    preview: None,
+                },
+                EnvConfig {
+                    name: "pr-1".to_string(),
+                    strategy: UpdateStrategy::Freeze,
+                    verify: None,
+                    preview: Some(PreviewConfig { base: "dev".to_string(), created: 1700000000 }),
                 },
             ]),
             projects: Vec::from([
@@ -242,19 +605,245 @@ mod tests {
                     name: "project-a".to_string(),
                     url: "github:chadac/project-a".to_string(),
                     path: Some(PathBuf::from("./project-a")),
+                    environments: None,
+                    exclude_environments: None,
+                    tags: None,
                     strategy: None,
+                    versions: None,
                 },
                 ProjectConfig {
                     name: "project-b".to_string(),
                     url: "github:chadac/project-b".to_string(),
                     path: Some(PathBuf::from("./subfolder/project-b")),
+                    environments: Some(vec!["dev".to_string(), "stage".to_string()]),
+                    exclude_environments: None,
+                    tags: None,
                     strategy: Some(BTreeMap::from([
                         ("stage".to_string(), UpdateStrategy::Freeze),
                     ])),
+                    versions: None,
                 },
             ]),
             default_env: "dev".to_string(),
+            sync: SyncMode::Rebase,
+            forge: ForgeConfig::Http { url: "http://localhost:8080/requests".to_string(), token_env: None },
+            policy: PolicyConfig { allow: vec!["github.com/chadac/*".to_string()], ..Default::default() },
         };
         let repr = toml::to_string(&config).unwrap();
+        let parsed: Config = toml::from_str(&repr).unwrap();
+        assert_eq!(parsed.sync, config.sync);
+        assert_eq!(parsed.forge, config.forge);
+        assert_eq!(parsed.policy, config.policy);
+        assert_eq!(parsed.environments[2].verify, Some(Verification::SignedTag));
+        assert_eq!(parsed.environments[3].preview.as_ref().map(|p| p.created), Some(1700000000));
+    }
+
+    fn config_with_override() -> Config {
+        let mut config = Config::new();
+        config.add_env("prod", UpdateStrategy::Freeze).unwrap();
+        config.projects.push(ProjectConfig {
+            name: "project-a".to_string(),
+            url: "github:chadac/project-a".to_string(),
+            path: None,
+            environments: Some(vec!["dev".to_string(), "prod".to_string()]),
+            exclude_environments: Some(vec!["prod".to_string()]),
+            tags: Some(vec!["backend".to_string()]),
+            strategy: Some(BTreeMap::from([
+                ("prod".to_string(), UpdateStrategy::Branch("release".to_string())),
+            ])),
+            versions: None,
+        });
+        config
+    }
+
+    #[test]
+    fn test_env_membership() {
+        let mut config = config_with_override();
+        config.add_env("stage", UpdateStrategy::Latest).unwrap();
+        let project = config.project_mut("project-a").unwrap();
+        assert!(project.in_env("dev"));
+        assert!(!project.in_env("prod"));
+        assert!(!project.in_env("stage"));
+        project.environments = None;
+        assert!(project.in_env("stage"));
+        assert!(!project.in_env("prod"));
+        assert_eq!(config.projects_in_env("prod").len(), 0);
+        assert_eq!(config.projects_in_env("stage").len(), 1);
+    }
+
+    #[test]
+    fn test_parse_strategy() {
+        assert!(matches!("latest".parse::<UpdateStrategy>().unwrap(), UpdateStrategy::Latest));
+        assert!(matches!("\"freeze\"".parse::<UpdateStrategy>().unwrap(), UpdateStrategy::Freeze));
+        assert!(matches!(
+            r#"{"branch": "main"}"#.parse::<UpdateStrategy>().unwrap(),
+            UpdateStrategy::Branch(b) if b == "main"
+        ));
+        assert!("nonsense".parse::<UpdateStrategy>().is_err());
+    }
+
+    #[test]
+    fn test_signature_check() -> Result<()> {
+        for tool in ["ssh-keygen", "gpg"] {
+            if std::process::Command::new(tool).arg("--help").output().is_err() {
+                eprintln!("skipping test_signature_check; {tool} is not installed");
+                return Ok(());
+            }
+        }
+        let tmp = tempdir::TempDir::new("signatures")?;
+        let (remote, work) = (tmp.path().join("remote.git"), tmp.path().join("work"));
+        // a keyring that only the commands run by this test see
+        let gnupg = tmp.path().join("gnupg");
+        std::fs::create_dir_all(&gnupg)?;
+        std::fs::set_permissions(&gnupg, std::os::unix::fs::PermissionsExt::from_mode(0o700))?;
+        let run = |cmd: &str, args: &[&str], cwd: &Path| -> Result<()> {
+            std::fs::create_dir_all(cwd)?;
+            let out = std::process::Command::new(cmd).args(args).env("GNUPGHOME", &gnupg).current_dir(cwd).output()?;
+            assert!(out.status.success(), "{cmd} {args:?}: {}", String::from_utf8_lossy(&out.stderr));
+            Ok(())
+        };
+        for key in ["trusted", "other"] {
+            run("ssh-keygen", &["-q", "-t", "ed25519", "-N", "", "-f", key], tmp.path())?;
+        }
+        let public = std::fs::read_to_string(tmp.path().join("trusted.pub"))?;
+        let allowed_signers = tmp.path().join("allowed_signers");
+        std::fs::write(&allowed_signers, format!("dev@example.com {public}"))?;
+
+        run("git", &["init", "-q", "--bare", "-b", "main"], &remote)?;
+        run("git", &["init", "-q", "-b", "main"], &work)?;
+        let signed = |key: Option<&str>, args: &[&str]| -> Result<String> {
+            let signing = key.map(|k| format!("user.signingkey={}", tmp.path().join(k).display())).unwrap_or_default();
+            let mut all = vec!["-c", "user.name=dev", "-c", "user.email=dev@example.com", "-c", "gpg.format=ssh"];
+            if key.is_some() {
+                all.extend(["-c", &signing]);
+            }
+            all.extend(args);
+            run("git", &all, &work)?;
+            Git::rev_parse("HEAD", &work)
+        };
+        let c1 = signed(Some("trusted"), &["commit", "-q", "-S", "--allow-empty", "-m", "c1"])?;
+        signed(Some("trusted"), &["tag", "-s", "-m", "v1.0", "v1.0"])?;
+        let c2 = signed(Some("other"), &["commit", "-q", "-S", "--allow-empty", "-m", "c2"])?;
+        signed(Some("other"), &["tag", "-s", "-m", "v1.1", "v1.1"])?;
+        let c3 = signed(None, &["commit", "-q", "--allow-empty", "-m", "c3"])?;
+        signed(None, &["tag", "v1.2"])?;
+        // GPG keys are never trusted, even if the local keyring trusts them
+        run("gpg", &["--batch", "--passphrase", "", "--quick-gen-key", "dev <dev@example.com>", "ed25519", "sign", "never"], tmp.path())?;
+        let gpg = ["-c", "gpg.format=openpgp", "-c", "user.signingkey=dev@example.com"];
+        let c4 = signed(None, &[&gpg[..], &["commit", "-q", "-S", "--allow-empty", "-m", "c4"]].concat())?;
+        signed(None, &[&gpg[..], &["tag", "-s", "-m", "v1.3", "v1.3"]].concat())?;
+        run("git", &["verify-commit", &c4], &work)?;
+        run("git", &["push", "-q", "--tags", remote.to_str().unwrap(), "main"], &work)?;
+
+        let url = format!("file:{}", remote.display());
+        // make the mirror check GPG signatures against the test keyring,
+        // which fully trusts the key
+        let wrapper = tmp.path().join("gpg-test");
+        std::fs::write(&wrapper, format!("#!/bin/sh\nGNUPGHOME='{}' exec gpg \"$@\"\n", gnupg.display()))?;
+        std::fs::set_permissions(&wrapper, std::os::unix::fs::PermissionsExt::from_mode(0o755))?;
+        run("git", &["config", "gpg.program", wrapper.to_str().unwrap()], &Git::mirror(&url)?)?;
+        let candidates = vec![c4.clone(), c3.clone(), c2.clone(), c1.clone()];
+        let check = |verify: Verification, locked: Option<&String>| SignatureCheck {
+            verify,
+            allowed_signers: allowed_signers.clone(),
+            locked: locked.cloned(),
+        };
+        assert_eq!(check(Verification::SignedCommit, None).pick(&candidates, &url)?, Some(c1.clone()));
+        assert_eq!(check(Verification::SignedTag, None).pick(&candidates, &url)?, Some(c1.clone()));
+        assert_eq!(check(Verification::SignedCommit, Some(&c2)).pick(&candidates, &url)?, Some(c2.clone()));
+        assert_eq!(check(Verification::SignedCommit, Some(&c1)).pick(&candidates[..1], &url)?, Some(c1.clone()));
+        assert!(check(Verification::SignedTag, None).pick(&candidates[..3], &url).is_err());
+        assert_eq!(check(Verification::SignedTag, None).pick(&[], &url)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_strategy_follows() {
+        let head = "refs/heads/main";
+        assert!(UpdateStrategy::Latest.follows("refs/heads/main", head));
+        assert!(!UpdateStrategy::Latest.follows("refs/heads/feature", head));
+        assert!(UpdateStrategy::Freeze.follows("refs/heads/feature", head));
+        assert!(UpdateStrategy::Branch("release".to_string()).follows("refs/heads/release", head));
+        assert!(!UpdateStrategy::Branch("release".to_string()).follows("refs/tags/release", head));
+        assert!(UpdateStrategy::LatestTag(Some("v1.*".to_string())).follows("refs/tags/v1.2", head));
+        assert!(!UpdateStrategy::LatestTag(Some("v1.*".to_string())).follows("refs/tags/v2.0", head));
+        assert!(UpdateStrategy::LatestTag(None).follows("refs/tags/anything", head));
+
+        let refs: Vec<GitRef> = ["HEAD", "refs/heads/main", "refs/tags/v1.0", "refs/tags/v1.1", "refs/tags/v2.0"].iter()
+            .enumerate()
+            .map(|(i, r)| GitRef { rev: i.to_string(), git_ref: r.to_string() })
+            .collect();
+        assert_eq!(UpdateStrategy::LatestTag(Some("v1.*".to_string())).candidates(&refs).unwrap(), vec!["3", "2"]);
+        assert_eq!(UpdateStrategy::Branch("main".to_string()).candidates(&refs).unwrap(), vec!["1"]);
+        assert!(UpdateStrategy::Freeze.candidates(&refs).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rename_env() {
+        let mut config = config_with_override();
+        config.rename_env("dev", "development").unwrap();
+        config.rename_env("prod", "production").unwrap();
+        assert_eq!(config.default_env, "development");
+        assert_eq!(config.environments(), vec!["development", "production"]);
+        let strategy = config.project("project-a").unwrap().strategy.as_ref().unwrap();
+        assert!(strategy.contains_key("production"));
+        assert!(!strategy.contains_key("prod"));
+        let project = config.project("project-a").unwrap();
+        assert!(project.in_env("development"));
+        assert!(!project.in_env("production"));
+        assert!(config.rename_env("development", "production").is_err());
+    }
+
+    #[test]
+    fn test_rm_env() {
+        let mut config = config_with_override();
+        assert!(config.rm_env("dev").is_err());
+        config.rm_env("prod").unwrap();
+        assert_eq!(config.environments(), vec!["dev"]);
+        let project = config.project("project-a").unwrap();
+        assert!(project.strategy.as_ref().unwrap().is_empty());
+        assert_eq!(project.environments, Some(vec!["dev".to_string()]));
+        assert_eq!(project.exclude_environments, Some(vec![]));
+        assert!(config.rm_env("prod").is_err());
+    }
+
+    #[test]
+    fn test_copy_env_projects() {
+        let mut config = config_with_override();
+        config.add_env("stage", UpdateStrategy::Latest).unwrap();
+        config.copy_env_projects("prod", "stage");
+        let project = config.project("project-a").unwrap();
+        assert!(matches!(project.strategy.as_ref().unwrap().get("stage"), Some(UpdateStrategy::Branch(b)) if b == "release"));
+        assert!(!project.in_env("stage"));
+        assert!(config.add_env("stage", UpdateStrategy::Latest).is_err());
+    }
+
+    #[test]
+    fn test_detects_duplicate_projects() {
+        let mut config = config_with_override();
+        let spelling = crate::flake::parse("git+https://github.com/Chadac/project-a.git").unwrap();
+        assert_eq!(config.get_project_by_flake_ref(spelling.as_ref()).unwrap().name, "project-a");
+        assert!(config.add_project("other", spelling.as_ref(), &None::<&str>).is_err());
+
+        let project_b = crate::flake::parse("github:chadac/project-b").unwrap();
+        assert!(config.add_project("project-a", project_b.as_ref(), &None::<&str>).is_err());
+        assert!(config.add_project("project-b", project_b.as_ref(), &None::<&str>).is_ok());
+
+        let removed = config.rm_project(crate::flake::parse("github:chadac/project-b/").unwrap().as_ref()).unwrap();
+        assert_eq!(removed.name, "project-b");
+    }
+
+    #[test]
+    fn test_tarball_versions() {
+        let mut versions = Some(vec!["1.9.0".to_string(), "1.10.0".to_string(), "2.0.0-rc1".to_string(), "1.2.0".to_string()]);
+        assert_eq!(UpdateStrategy::Latest.get_version(&versions).unwrap(), Some("2.0.0-rc1".to_string()));
+        assert_eq!(UpdateStrategy::LatestTag(Some("1.*".to_string())).get_version(&versions).unwrap(), Some("1.10.0".to_string()));
+        assert_eq!(UpdateStrategy::Freeze.get_version(&versions).unwrap(), None);
+        versions.as_mut().unwrap().push("2.0.0".to_string());
+        assert_eq!(UpdateStrategy::Latest.get_version(&versions).unwrap(), Some("2.0.0".to_string()));
+        assert_eq!(UpdateStrategy::LatestTag(Some("2.*".to_string())).get_version(&versions).unwrap(), Some("2.0.0".to_string()));
+        assert!(UpdateStrategy::LatestTag(Some("3.*".to_string())).get_version(&versions).is_err());
+        assert!(UpdateStrategy::Branch("main".to_string()).get_version(&versions).is_err());
+        assert!(UpdateStrategy::Latest.get_version(&None).is_err());
     }
 }
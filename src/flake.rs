@@ -10,88 +10,346 @@ use querystring::{querify, stringify};
 use super::config::UpdateStrategy;
 use crate::lockfile::{FlakeType, InputSpec};
 
-fn split_once(msg: &str, split: &str) -> Result<(String, String)> {
-    let mut parts = msg.split(split);
-    let first = parts.next().context("failed to parse")?;
-    let rest = parts.fold(String::new(), |a, b| a + b);
-    Ok((first.to_string(), rest))
+/// splits <scheme>:<url> into a (<scheme>, <url>) tuple on the first `:`,
+/// returning None for scheme-less references
+fn split_scheme(url: &str) -> Option<(String, String)> {
+    let re = Regex::new("^([a-zA-Z][a-zA-Z0-9+.-]*):(.*)$").unwrap();
+    let m = re.captures(url)?;
+    Some((m.get(1).unwrap().as_str().to_string(), m.get(2).unwrap().as_str().to_string()))
 }
 
-/// splits <scheme>:<url> into a (<scheme>, <url>) tuple
-fn split_scheme(url: &str) -> Result<(String, String)> {
-    split_once(url, ":")
+/// true if a string looks like a commit hash rather than a branch or tag name
+pub fn is_rev(s: &str) -> bool {
+    (s.len() == 40 || s.len() == 64) && s.chars().all(|c| c.is_ascii_hexdigit())
 }
 
+/// true if a URL points at an archive that Nix unpacks as a tarball
+fn is_archive(path: &str) -> bool {
+    ARCHIVE_EXTENSIONS.iter().any(|ext| path.ends_with(ext))
+}
+
+const ARCHIVE_EXTENSIONS: [&str; 7] = [".zip", ".tar", ".tgz", ".tar.gz", ".tar.xz", ".tar.bz2", ".tar.zst"];
+
 pub trait FlakeRef {
     fn flake_url(&self) -> String;
     fn flake_type(&self) -> FlakeType;
     fn git_remote_url(&self) -> Option<String>;
+    /// URL of the remote Mercurial repository, for `hg+` references
+    fn hg_remote_url(&self) -> Option<String> {
+        None
+    }
     fn arg(&self, arg: &str) -> Option<String>;
 
     fn with_rev(&self, rev: &str) -> Rc<dyn FlakeRef>;
 
+    /// Returns the reference in a canonical spelling: explicit scheme
+    /// prefixes, sorted parameters and, for case-insensitive forges,
+    /// lowercase owner and repository names.
+    fn canonical(&self) -> Rc<dyn FlakeRef>;
+
+    fn canonical_url(&self) -> String {
+        self.canonical().flake_url()
+    }
+
+    /// Identifies the repository the flake is fetched from independently of
+    /// how it is spelled, i.e. `github:foo/bar` and
+    /// `git+https://github.com/foo/bar.git` share `github.com/foo/bar`.
+    fn repository(&self) -> Option<String> {
+        self.git_remote_url().map(|url| normalize_remote(&url))
+    }
+
+    /// Returns true if both references point at the same flake (repository
+    /// and subdirectory), regardless of the revision they select.
+    fn same_source(&self, other: &dyn FlakeRef) -> bool {
+        let same_dir = normalize_dir(self.arg("dir")) == normalize_dir(other.arg("dir"));
+        match (self.repository(), other.repository()) {
+            (Some(a), Some(b)) => a == b && same_dir,
+            _ if self.flake_type() == FlakeType::Indirect => {
+                other.flake_type() == FlakeType::Indirect && self.arg("id") == other.arg("id") && same_dir
+            },
+            _ => {
+                let base = |url: String| url.split('?').next().unwrap().to_string();
+                base(self.canonical_url()) == base(other.canonical_url()) && same_dir
+            },
+        }
+    }
+
+    /// Returns true if both references resolve to the same source at the
+    /// same revision.
+    fn equivalent(&self, other: &dyn FlakeRef) -> bool {
+        match (self.repository(), other.repository()) {
+            (Some(_), Some(_)) => {
+                let git_ref = |r: Option<String>| r.map(|r| r.trim_start_matches("refs/heads/").to_string());
+                self.same_source(other)
+                    && git_ref(self.arg("ref")) == git_ref(other.arg("ref"))
+                    && self.arg("rev") == other.arg("rev")
+            },
+            _ => self.canonical_url() == other.canonical_url(),
+        }
+    }
+
     /// try to infer a name for the flake
     fn infer_name(&self) -> Option<String> {
-        self.arg("repo")
+        let subdir = normalize_dir(self.arg("dir"));
+        if !subdir.is_empty() {
+            return subdir.rsplit('/').next().map(|d| d.to_string());
+        }
+        let name = match self.flake_type() {
+            FlakeType::GitHub | FlakeType::GitLab | FlakeType::SourceHut => self.arg("repo"),
+            FlakeType::Indirect => self.arg("id"),
+            FlakeType::Path => self.arg("path"),
+            _ => self.arg("url").map(|url| url.split('?').next().unwrap().to_string()),
+        }?;
+        let base = name.trim_end_matches('/').rsplit(['/', ':']).next()?;
+        let base = ARCHIVE_EXTENSIONS.iter()
+            .chain([".git"].iter())
+            .find_map(|ext| base.strip_suffix(ext))
+            .unwrap_or(base);
+        Some(base.to_string()).filter(|b| !b.is_empty() && b != ".")
     }
 
+    /// Converts the reference to its attribute set representation, as used in
+    /// the `original` and `locked` entries of a lockfile.
     fn input_spec(&self) -> InputSpec {
-        InputSpec {
-            flake_type: self.flake_type(),
-            nar_hash: None,
-            url: Some(self.flake_url()),
-            owner: self.arg("owner"),
-            repo: self.arg("repo"),
+        let flake_type = self.flake_type();
+        let number = |name: &str| self.arg(name).and_then(|n| n.parse().ok());
+        let mut spec = InputSpec {
+            flake_type: flake_type.clone(),
+            nar_hash: self.arg("narHash"),
+            id: None,
+            path: None,
+            url: None,
+            owner: None,
+            repo: None,
+            host: None,
             dir: self.arg("dir"),
             rev: self.arg("rev"),
             git_ref: self.arg("ref"),
-            rev_count: None,
-            last_modified: None,
+            rev_count: number("revCount"),
+            last_modified: number("lastModified"),
+        };
+        match flake_type {
+            FlakeType::GitHub | FlakeType::GitLab | FlakeType::SourceHut => {
+                spec.owner = self.arg("owner");
+                spec.repo = self.arg("repo");
+                spec.host = self.arg("host").filter(|h| h != default_host(&flake_type));
+            },
+            FlakeType::Indirect => spec.id = self.arg("id"),
+            FlakeType::Path => spec.path = self.arg("path"),
+            FlakeType::Git | FlakeType::Mercurial | FlakeType::Tarball | FlakeType::File => {
+                spec.url = self.arg("url");
+            },
         }
+        spec
+    }
+}
+
+/// The host a `github:`, `gitlab:` or `sourcehut:` reference points at when
+/// no `host` parameter is given.
+fn default_host(flake_type: &FlakeType) -> &'static str {
+    match flake_type {
+        FlakeType::GitLab => "gitlab.com",
+        FlakeType::SourceHut => "git.sr.ht",
+        _ => "github.com",
+    }
+}
+
+/// Parameters that are represented as attributes of their own in an
+/// `InputSpec`, rather than as part of its `url`.
+const SPEC_PARAMS: [&str; 6] = ["dir", "narHash", "ref", "rev", "revCount", "lastModified"];
+
+/// Formats the `url` attribute of an `InputSpec`, keeping any parameters
+/// that have no attribute of their own.
+fn spec_url(scheme: &str, server: &Option<String>, path: &str, params: &[(String, String)]) -> String {
+    let params: Vec<(String, String)> = params.iter()
+        .filter(|(k, _)| !SPEC_PARAMS.contains(&k.as_str()))
+        .cloned()
+        .collect();
+    format!("{}{}", fmt_server_url(scheme, server, path), params_to_string(&params))
+}
+
+/// Hosts whose repository paths are case-insensitive.
+pub const CASE_INSENSITIVE_HOSTS: [&str; 2] = ["github.com", "gitlab.com"];
+
+/// Reduces a git remote URL to `<host>/<path>`, dropping the transport,
+/// user, port, trailing slashes and `.git` suffix.
+pub fn normalize_remote(url: &str) -> String {
+    let trim = |p: &str| p.trim_end_matches('/').trim_end_matches(".git").to_string();
+    if let Some(path) = url.strip_prefix("file:") {
+        return format!("file:{}", trim(path.trim_start_matches("//")));
+    }
+    let re = Regex::new("^(?:[a-z+]+://)?(?:[^@/]+@)?([^/:]+)(?::[0-9]+)?[:/]?(.*)$").unwrap();
+    match re.captures(url) {
+        Some(m) => {
+            let host = m.get(1).unwrap().as_str().to_lowercase();
+            let path = trim(m.get(2).unwrap().as_str());
+            if CASE_INSENSITIVE_HOSTS.contains(&host.as_str()) {
+                format!("{host}/{}", path.to_lowercase())
+            } else {
+                format!("{host}/{path}")
+            }
+        },
+        None => trim(url),
     }
 }
 
+fn normalize_dir(dir: Option<String>) -> String {
+    dir.map(|d| d.trim_start_matches("./").trim_matches('/').to_string()).unwrap_or_default()
+}
+
+fn sorted_params(params: &[(String, String)]) -> Vec<(String, String)> {
+    let mut sorted = params.to_vec();
+    sorted.sort();
+    sorted
+}
+
+/// Parses a flake reference in any of the URL-like forms accepted by Nix.
+///
+/// Scheme-less references are either paths (starting with `.` or `/`) or
+/// indirect references into the flake registry.
 pub fn parse(url: &str) -> Result<Rc<dyn FlakeRef>> {
-    let (scheme, url) = split_scheme(url)?;
-    let result: Rc<dyn FlakeRef> = match (scheme.as_str(), url) {
-        ("flake", rest) => FlakeIndirect::parse(&rest)?,
-        ("path", rest) => FlakePath::parse(&rest)?,
-        ("git+http", rest) => GitUrl::parse("http", &rest)?,
-        ("git+https", rest) => GitUrl::parse("https", &rest)?,
-        ("git+ssh", rest) => GitUrl::parse("ssh", &rest)?,
-        ("git+file", rest) => GitUrl::parse("file", &rest)?,
-        ("mc+http", rest) => MercurialUrl::parse("http", &rest)?,
-        ("mc+https", rest) => MercurialUrl::parse("https", &rest)?,
-        ("mc+ssh", rest) => MercurialUrl::parse("ssh", &rest)?,
-        ("mc+file", rest) => MercurialUrl::parse("file", &rest)?,
-        ("tarball+http", rest) => TarballUrl::parse("http", &rest)?,
-        ("tarball+https", rest) => TarballUrl::parse("https", &rest)?,
-        ("tarball+file", rest) => TarballUrl::parse("file", &rest)?,
-        ("github", rest) => SimpleGitUrl::parse("github", "github.com/", &rest)?,
-        ("gitlab", rest) => SimpleGitUrl::parse("gitlab", "gitlab.com/", &rest)?,
-        ("sourcehut", rest) => SimpleGitUrl::parse("sourcehut", "git.sr.ht/~", &rest)?,
-        (scheme, _) => bail!("unrecognized flake scheme: '{}'", scheme)
+    if url.starts_with('.') || url.starts_with('/') {
+        return FlakePath::parse(url, false);
+    }
+    let (scheme, rest) = match split_scheme(url) {
+        Some(s) => s,
+        None => return FlakeIndirect::parse(url, false),
+    };
+    let (prefix, transport) = match scheme.split_once('+') {
+        Some((p, t)) => (p, Some(t)),
+        None => (scheme.as_str(), None),
+    };
+    let result: Rc<dyn FlakeRef> = match (prefix, transport) {
+        ("flake", None) => FlakeIndirect::parse(&rest, true)?,
+        ("path", None) => FlakePath::parse(&rest, true)?,
+        ("git", Some(t @ ("http" | "https" | "ssh" | "git" | "file"))) => GitUrl::parse(t, &rest)?,
+        (p @ ("hg" | "mc"), Some(t @ ("http" | "https" | "ssh" | "file"))) => MercurialUrl::parse(p, t, &rest)?,
+        ("tarball", Some(t @ ("http" | "https" | "file"))) => TarballUrl::parse(true, t, &rest)?,
+        ("file", Some(t @ ("http" | "https" | "file"))) => FileUrl::parse(true, t, &rest)?,
+        (t @ ("http" | "https" | "file"), None) => {
+            let (_, path, _) = parse_server_url(&rest)?;
+            if is_archive(&path) {
+                TarballUrl::parse(false, t, &rest)?
+            } else {
+                FileUrl::parse(false, t, &rest)?
+            }
+        },
+        ("github", None) => SimpleGitUrl::parse("github", &rest)?,
+        ("gitlab", None) => SimpleGitUrl::parse("gitlab", &rest)?,
+        ("sourcehut", None) => SimpleGitUrl::parse("sourcehut", &rest)?,
+        _ => bail!("unrecognized flake scheme: '{}'", scheme)
     };
     Ok(result)
 }
 
+/// Builds a flake reference from its attribute set representation, as used
+/// in the `original` and `locked` entries of a lockfile.
+pub fn from_input_spec(spec: &InputSpec) -> Result<Rc<dyn FlakeRef>> {
+    let field = |value: &Option<String>, name: &str| value.clone()
+        .with_context(|| format!("input of type '{}' is missing '{name}'", to_variant_name(&spec.flake_type).unwrap_or("?")));
+    let mut params = Vec::new();
+    let mut param = |name: &str, value: Option<String>| {
+        if let Some(v) = value { params.push((name.to_string(), v)); }
+    };
+    let url = match spec.flake_type {
+        FlakeType::GitHub | FlakeType::GitLab | FlakeType::SourceHut => {
+            param("host", spec.host.clone());
+            let rev_or_ref = match (&spec.git_ref, &spec.rev) {
+                (Some(_), Some(_)) => {
+                    param("ref", spec.git_ref.clone());
+                    param("rev", spec.rev.clone());
+                    None
+                },
+                (r, None) | (None, r) => r.as_ref(),
+            };
+            format!(
+                "{scheme}:{owner}/{repo}{rev_or_ref}",
+                scheme=to_variant_name(&spec.flake_type)?,
+                owner=field(&spec.owner, "owner")?,
+                repo=field(&spec.repo, "repo")?,
+                rev_or_ref=rev_or_ref.map_or("".to_string(), |r| format!("/{r}")),
+            )
+        },
+        FlakeType::Indirect => {
+            let suffix: String = [&spec.git_ref, &spec.rev].iter()
+                .filter_map(|s| s.as_ref().map(|s| format!("/{s}")))
+                .collect();
+            format!("flake:{}{suffix}", field(&spec.id, "id")?)
+        },
+        _ => {
+            param("ref", spec.git_ref.clone());
+            param("rev", spec.rev.clone());
+            match spec.flake_type {
+                FlakeType::Git => format!("git+{}", field(&spec.url, "url")?),
+                FlakeType::Mercurial => format!("hg+{}", field(&spec.url, "url")?),
+                FlakeType::Tarball => format!("tarball+{}", field(&spec.url, "url")?),
+                FlakeType::File => format!("file+{}", field(&spec.url, "url")?),
+                _ => format!("path:{}", field(&spec.path, "path")?),
+            }
+        },
+    };
+    param("dir", spec.dir.clone());
+    param("narHash", spec.nar_hash.clone());
+    param("revCount", spec.rev_count.map(|n| n.to_string()));
+    param("lastModified", spec.last_modified.map(|n| n.to_string()));
+    let params = params_to_string(&params);
+    match url.contains('?') {
+        true => parse(&format!("{url}{}", params.replacen('?', "&", 1))),
+        false => parse(&format!("{url}{params}")),
+    }
+}
+
+/// Builds a flake reference from the URL of a git remote, as returned by
+/// `git remote get-url`.
+///
+/// HTTPS remotes on GitHub use the `github:` shorthand; scp-like remotes
+/// such as `git@host:owner/repo.git` are converted to `git+ssh`.
+pub fn from_git_remote(remote: &str, dir: Option<&str>) -> Result<Rc<dyn FlakeRef>> {
+    let scp = Regex::new("^([^/:@]+@)?([^/:]+):([^/].*)$")?;
+    let url = if let Some(rest) = remote.strip_prefix("https://github.com/") {
+        format!("github:{}", rest.trim_end_matches('/').trim_end_matches(".git"))
+    } else if remote.contains("://") {
+        format!("git+{remote}")
+    } else if remote.starts_with('/') {
+        format!("git+file:{remote}")
+    } else if let Some(m) = scp.captures(remote) {
+        format!(
+            "git+ssh://{user}{host}/{path}",
+            user=m.get(1).map_or("", |s| s.as_str()),
+            host=m.get(2).unwrap().as_str(),
+            path=m.get(3).unwrap().as_str(),
+        )
+    } else {
+        bail!("unrecognized git remote '{remote}'");
+    };
+    let url = match dir {
+        Some(d) if !d.is_empty() => format!("{url}?dir={d}"),
+        _ => url,
+    };
+    parse(&url)
+}
+
 /// format:
-/// [flake:]<flake-id>(/<rev-or-ref>(/rev)?)?
+/// (flake:)?<flake-id>(/<rev-or-ref>(/rev)?)?(\?<params>)?
 #[derive(Clone, PartialEq, Debug)]
 pub struct FlakeIndirect {
+    explicit: bool,
     flake_id: String,
     rev_or_ref: Option<String>,
-    rev: Option<String>
+    rev: Option<String>,
+    params: Vec<(String, String)>,
 }
 
 impl FlakeIndirect {
-    fn parse(url: &str) -> Result<Rc<dyn FlakeRef>> {
-        let re = Regex::new("([^/]+)(?:/([^/]+)(?:/([^/]+))?)?")?;
-        let m = re.captures(url).context("failed to parse indirect flake url")?;
+    fn parse(url: &str, explicit: bool) -> Result<Rc<dyn FlakeRef>> {
+        let re = Regex::new("^([a-zA-Z][a-zA-Z0-9_-]*)(?:/([^/?]+)(?:/([^/?]+))?)?(?:[?](.*))?$")?;
+        let m = re.captures(url).with_context(|| format!("failed to parse indirect flake url '{url}'"))?;
         Ok(Rc::new(Self {
+            explicit,
             flake_id: m.get(1).unwrap().as_str().to_string(),
             rev_or_ref: m.get(2).map(|s| s.as_str().to_string()),
             rev: m.get(3).map(|s| s.as_str().to_string()),
+            params: parse_params(m.get(4)),
         }))
     }
 }
@@ -99,16 +357,28 @@ impl FlakeIndirect {
 impl FlakeRef for FlakeIndirect {
     fn flake_url(&self) -> String {
         format!(
-            "flake:{flake_id}{rev_or_ref}{rev}",
+            "{prefix}{flake_id}{rev_or_ref}{rev}{params}",
+            prefix=if self.explicit { "flake:" } else { "" },
             flake_id=self.flake_id,
             rev_or_ref=self.rev_or_ref.as_ref().map(|s| format!("/{}", s)).unwrap_or("".to_string()),
-            rev=self.rev.as_ref().map(|s| format!("/{}", s)).unwrap_or("".to_string())
+            rev=self.rev.as_ref().map(|s| format!("/{}", s)).unwrap_or("".to_string()),
+            params=params_to_string(&self.params),
         )
     }
 
     fn with_rev(&self, rev: &str) -> Rc<dyn FlakeRef> {
         let mut copy = self.clone();
-        copy.rev = Some(rev.to_string());
+        match &copy.rev_or_ref {
+            Some(r) if !is_rev(r) => copy.rev = Some(rev.to_string()),
+            _ => copy.rev_or_ref = Some(rev.to_string()),
+        }
+        Rc::new(copy)
+    }
+
+    fn canonical(&self) -> Rc<dyn FlakeRef> {
+        let mut copy = self.clone();
+        copy.explicit = true;
+        copy.params = sorted_params(&self.params);
         Rc::new(copy)
     }
 
@@ -122,17 +392,19 @@ impl FlakeRef for FlakeIndirect {
 
     fn arg(&self, arg: &str) -> Option<String> {
         match arg {
-            "ref" => self.rev_or_ref.clone(),
-            "rev" => self.rev.clone(),
-            _ => None
+            "id" => Some(self.flake_id.clone()),
+            "ref" => self.rev_or_ref.clone().filter(|r| !is_rev(r)),
+            "rev" => self.rev.clone().or(self.rev_or_ref.clone().filter(|r| is_rev(r))),
+            _ => find_param(&self.params, arg),
         }
     }
 }
 
 /// format:
-/// path:<path>(\?<params>)?
+/// (path:)?<path>(\?<params>)?
 #[derive(Clone, PartialEq, Debug)]
 pub struct FlakePath {
+    explicit: bool,
     path: String,
     params: Vec<(String, String)>,
 }
@@ -152,16 +424,25 @@ fn params_to_string(params: &Vec<(String, String)>) -> String {
     }
 }
 
+fn parse_params(qs: Option<regex::Match>) -> Vec<(String, String)> {
+    querify(qs.map_or("", |s| s.as_str()))
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn find_param(params: &[(String, String)], arg: &str) -> Option<String> {
+    params.iter().find(|(k, _)| k == arg).map(|(_, v)| v.to_string())
+}
+
 impl FlakePath {
-    pub fn parse(url: &str) -> Result<Rc<dyn FlakeRef>> {
-        let re = Regex::new("([^?]+)(?:[?](.+))?$")?;
+    pub fn parse(url: &str, explicit: bool) -> Result<Rc<dyn FlakeRef>> {
+        let re = Regex::new("^([^?]+)(?:[?](.*))?$")?;
         let m = re.captures(url).context("failed to parse flake path")?;
         Ok(Rc::new(FlakePath {
+            explicit,
             path: m.get(1).unwrap().as_str().to_string(),
-            params: querify(m.get(2).map_or("", |s| s.as_str()))
-                .iter()
-                .map(|(k, v)| (k.to_string(), v.to_string()))
-                .collect(),
+            params: parse_params(m.get(2)),
         }))
     }
 }
@@ -169,7 +450,8 @@ impl FlakePath {
 impl FlakeRef for FlakePath {
     fn flake_url(&self) -> String {
         format!(
-            "path:{path}{params}",
+            "{prefix}{path}{params}",
+            prefix=if self.explicit { "path:" } else { "" },
             path=self.path,
             params=params_to_string(&self.params),
         )
@@ -177,6 +459,15 @@ impl FlakeRef for FlakePath {
     fn with_rev(&self, rev: &str) -> Rc<dyn FlakeRef> {
         Rc::new(self.clone())
     }
+    fn canonical(&self) -> Rc<dyn FlakeRef> {
+        let mut copy = self.clone();
+        copy.explicit = true;
+        if copy.path.len() > 1 {
+            copy.path = copy.path.trim_end_matches('/').to_string();
+        }
+        copy.params = sorted_params(&self.params);
+        Rc::new(copy)
+    }
     fn flake_type(&self) -> FlakeType {
         FlakeType::Path
     }
@@ -184,23 +475,30 @@ impl FlakeRef for FlakePath {
         None
     }
     fn arg(&self, arg: &str) -> Option<String> {
-        self.params.iter().find(|(k, _)| k == arg).map(|(_, v)| v.to_string())
+        match arg {
+            "path" => Some(self.path.clone()),
+            _ => find_param(&self.params, arg),
+        }
     }
 }
 
 fn parse_server_url(url: &str) -> Result<(Option<String>, String, Vec<(String, String)>)> {
-    let re = Regex::new("(?://([^/]+))?([^?]+)(?:[?](.+))?")?;
+    let re = Regex::new("^(?://([^/?]+))?([^?]*)(?:[?](.*))?$")?;
     let m = re.captures(url).with_context(|| format!("failed to parse server url {url}"))?;
     Ok((
         m.get(1).map(|s| s.as_str().to_string()),
         m.get(2).unwrap().as_str().to_string(),
-        querify(m.get(3).map_or("", |s| s.as_str()))
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect()
+        parse_params(m.get(3)),
     ))
 }
 
+fn fmt_server_url(scheme: &str, server: &Option<String>, path: &str) -> String {
+    format!(
+        "{scheme}:{server}{path}",
+        server=server.as_ref().map_or("".to_string(), |s| format!("//{s}")),
+    )
+}
+
 /// format:
 /// git(+http|+https|+ssh|+git|+file):(//<server>)?<path>(\?<params>)?
 #[derive(Clone, PartialEq, Debug)]
@@ -226,13 +524,18 @@ impl GitUrl {
 impl FlakeRef for GitUrl {
     fn flake_url(&self) -> String {
         format!(
-            "git+{scheme}:{server}{path}{params}",
-            scheme=self.scheme,
-            server=self.server.as_ref().map_or("".to_string(), |s| format!("//{s}")),
-            path=self.path,
+            "git+{url}{params}",
+            url=fmt_server_url(&self.scheme, &self.server, &self.path),
             params=params_to_string(&self.params),
         )
     }
+    fn canonical(&self) -> Rc<dyn FlakeRef> {
+        let mut copy = self.clone();
+        copy.server = self.server.as_ref().map(|s| s.to_lowercase());
+        copy.path = self.path.trim_end_matches('/').to_string();
+        copy.params = sorted_params(&self.params);
+        Rc::new(copy)
+    }
     fn flake_type(&self) -> FlakeType {
         FlakeType::Git
     }
@@ -243,22 +546,24 @@ impl FlakeRef for GitUrl {
         Rc::new(clone)
     }
     fn git_remote_url(&self) -> Option<String> {
-        Some(format!(
-            "{scheme}:{server}{path}",
-            scheme=self.scheme,
-            server=self.server.as_ref().map_or("".to_string(), |s| format!("//{s}")),
-            path=self.path
-        ))
+        Some(fmt_server_url(&self.scheme, &self.server, &self.path))
     }
     fn arg(&self, arg: &str) -> Option<String> {
-        self.params.iter().find(|(k, _)| k == arg).map(|(_, v)| v.to_string())
+        match arg {
+            "url" => Some(spec_url(&self.scheme, &self.server, &self.path, &self.params)),
+            _ => find_param(&self.params, arg),
+        }
     }
 }
 
 /// format:
-/// mc(+http|+https|+ssh|+file):(//<server>)?<path>(\?<params>)?
+/// (hg|mc)(+http|+https|+ssh|+file):(//<server>)?<path>(\?<params>)?
+///
+/// `mc+` is accepted for compatibility with older workspaces; Nix itself
+/// only recognizes `hg+`.
 #[derive(Clone, PartialEq, Debug)]
 pub struct MercurialUrl {
+    prefix: String,
     scheme: String,
     server: Option<String>,
     path: String,
@@ -266,9 +571,10 @@ pub struct MercurialUrl {
 }
 
 impl MercurialUrl {
-    pub fn parse(scheme: &str, url: &str) -> Result<Rc<dyn FlakeRef>> {
+    pub fn parse(prefix: &str, scheme: &str, url: &str) -> Result<Rc<dyn FlakeRef>> {
         let (server, path, params) = parse_server_url(url)?;
         Ok(Rc::new(MercurialUrl {
+            prefix: prefix.to_string(),
             scheme: scheme.to_string(),
             server: server,
             path: path,
@@ -280,19 +586,29 @@ impl MercurialUrl {
 impl FlakeRef for MercurialUrl {
     fn flake_url(&self) -> String {
         format!(
-            "mc+{scheme}:{server}{path}{params}",
-            scheme=self.scheme,
-            server=self.server.as_ref().map_or("".to_string(), |s| format!("//{s}")),
-            path=self.path,
+            "{prefix}+{url}{params}",
+            prefix=self.prefix,
+            url=fmt_server_url(&self.scheme, &self.server, &self.path),
             params=params_to_string(&self.params),
         )
     }
+    fn canonical(&self) -> Rc<dyn FlakeRef> {
+        let mut copy = self.clone();
+        copy.prefix = "hg".to_string();
+        copy.server = self.server.as_ref().map(|s| s.to_lowercase());
+        copy.path = self.path.trim_end_matches('/').to_string();
+        copy.params = sorted_params(&self.params);
+        Rc::new(copy)
+    }
     fn flake_type(&self) -> FlakeType {
         FlakeType::Mercurial
     }
     fn git_remote_url(&self) -> Option<String> {
         None
     }
+    fn hg_remote_url(&self) -> Option<String> {
+        Some(fmt_server_url(&self.scheme, &self.server, &self.path))
+    }
     fn with_rev(&self, rev: &str) -> Rc<dyn FlakeRef> {
         let mut clone = self.clone();
         clone.params.retain(|(k, _)| k != "rev");
@@ -300,25 +616,58 @@ impl FlakeRef for MercurialUrl {
         Rc::new(clone)
     }
     fn arg(&self, arg: &str) -> Option<String> {
-        self.params.iter().find(|(k, _)| k == arg).map(|(_, v)| v.to_string())
+        match arg {
+            "url" => Some(spec_url(&self.scheme, &self.server, &self.path, &self.params)),
+            _ => find_param(&self.params, arg),
+        }
     }
 }
 
+/// Placeholder for the version in the URL of a tarball project, as in
+/// `https://example.com/foo-{version}.tar.gz`.
+pub const VERSION_VAR: &str = "{version}";
+
+/// true if the URL is a template that `with_rev` substitutes a version into
+pub fn is_template(url: &str) -> bool {
+    url.contains(VERSION_VAR)
+}
+
+/// Extracts the version that was substituted into a URL template to produce
+/// `url`.
+pub fn template_version(template: &str, url: &str) -> Option<String> {
+    let pattern = template.split(VERSION_VAR)
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join("(.+)");
+    let m = Regex::new(&format!("^{pattern}$")).ok()?.captures(url)?;
+    let mut versions = m.iter().skip(1).flatten().map(|v| v.as_str());
+    let version = versions.next()?;
+    versions.all(|v| v == version).then(|| version.to_string())
+}
+
 /// format:
-/// tarball(+http|+https|file)://<url>
+/// (tarball+)?(http|https|file):(//<server>)?<path>(\?<params>)?
+///
+/// Without the `tarball+` prefix, the path must end in an archive extension
+/// such as `.tar.gz` or `.zip`.
 #[derive(Clone, PartialEq, Debug)]
 pub struct TarballUrl {
+    explicit: bool,
     scheme: String,
-    url: String,
+    server: Option<String>,
+    path: String,
+    params: Vec<(String, String)>,
 }
 
 impl TarballUrl {
-    pub fn parse(scheme: &str, url: &str) -> Result<Rc<dyn FlakeRef>> {
-        let re = Regex::new("//(.+)")?;
-        let m = re.captures(url).with_context(|| format!("could not parse tarball url: '{url}'"))?;
+    pub fn parse(explicit: bool, scheme: &str, url: &str) -> Result<Rc<dyn FlakeRef>> {
+        let (server, path, params) = parse_server_url(url)?;
         Ok(Rc::new(TarballUrl {
+            explicit,
             scheme: scheme.to_string(),
-            url: m.get(1).map(|s| s.as_str().to_string()).unwrap(),
+            server,
+            path,
+            params,
         }))
     }
 }
@@ -326,36 +675,111 @@ impl TarballUrl {
 impl FlakeRef for TarballUrl {
     fn flake_url(&self) -> String {
         format!(
-            "tarball+{scheme}://{url}",
-            scheme=self.scheme,
-            url=self.url,
+            "{prefix}{url}{params}",
+            prefix=if self.explicit { "tarball+" } else { "" },
+            url=fmt_server_url(&self.scheme, &self.server, &self.path),
+            params=params_to_string(&self.params),
         )
     }
+    fn canonical(&self) -> Rc<dyn FlakeRef> {
+        let mut copy = self.clone();
+        copy.explicit = true;
+        copy.server = self.server.as_ref().map(|s| s.to_lowercase());
+        copy.params = sorted_params(&self.params);
+        Rc::new(copy)
+    }
     fn flake_type(&self) -> FlakeType {
         FlakeType::Tarball
     }
     fn git_remote_url(&self) -> Option<String> {
         None
     }
+    /// Substitutes the version into a URL template; tarballs without a
+    /// template have no revisions to choose from.
     fn with_rev(&self, rev: &str) -> Rc<dyn FlakeRef> {
-        Rc::new(self.clone())
+        let mut clone = self.clone();
+        clone.path = self.path.replace(VERSION_VAR, rev);
+        clone.params = self.params.iter()
+            .map(|(k, v)| (k.to_string(), v.replace(VERSION_VAR, rev)))
+            .collect();
+        Rc::new(clone)
     }
     fn arg(&self, arg: &str) -> Option<String> {
+        match arg {
+            "url" => Some(spec_url(&self.scheme, &self.server, &self.path, &self.params)),
+            _ => find_param(&self.params, arg),
+        }
+    }
+}
+
+/// format:
+/// (file+)?(http|https|file):(//<server>)?<path>(\?<params>)?
+///
+/// Without the `file+` prefix, the path must not end in an archive
+/// extension.
+#[derive(Clone, PartialEq, Debug)]
+pub struct FileUrl {
+    explicit: bool,
+    scheme: String,
+    server: Option<String>,
+    path: String,
+    params: Vec<(String, String)>,
+}
+
+impl FileUrl {
+    pub fn parse(explicit: bool, scheme: &str, url: &str) -> Result<Rc<dyn FlakeRef>> {
+        let (server, path, params) = parse_server_url(url)?;
+        Ok(Rc::new(FileUrl {
+            explicit,
+            scheme: scheme.to_string(),
+            server,
+            path,
+            params,
+        }))
+    }
+}
+
+impl FlakeRef for FileUrl {
+    fn flake_url(&self) -> String {
+        format!(
+            "{prefix}{url}{params}",
+            prefix=if self.explicit { "file+" } else { "" },
+            url=fmt_server_url(&self.scheme, &self.server, &self.path),
+            params=params_to_string(&self.params),
+        )
+    }
+    fn canonical(&self) -> Rc<dyn FlakeRef> {
+        let mut copy = self.clone();
+        copy.explicit = true;
+        copy.server = self.server.as_ref().map(|s| s.to_lowercase());
+        copy.params = sorted_params(&self.params);
+        Rc::new(copy)
+    }
+    fn flake_type(&self) -> FlakeType {
+        FlakeType::File
+    }
+    fn git_remote_url(&self) -> Option<String> {
         None
     }
+    fn with_rev(&self, rev: &str) -> Rc<dyn FlakeRef> {
+        Rc::new(self.clone())
+    }
+    fn arg(&self, arg: &str) -> Option<String> {
+        match arg {
+            "url" => Some(spec_url(&self.scheme, &self.server, &self.path, &self.params)),
+            _ => find_param(&self.params, arg),
+        }
+    }
 }
 
 fn parse_simple_url(url: &str) -> Result<(String, String, Option<String>, Vec<(String, String)>)> {
-    let re = Regex::new("([^/]+)/([^/]+)(?:/([^?]+))?(?:[?](.+))?")?;
+    let re = Regex::new("^([^/?]+)/([^/?]+)(?:/([^?]*))?(?:[?](.*))?$")?;
     let m = re.captures(url).with_context(|| format!("could not parse simple url: '{url}'"))?;
     Ok((
         m.get(1).map(|s| s.as_str().to_string()).unwrap(),
         m.get(2).map(|s| s.as_str().to_string()).unwrap(),
-        m.get(3).map(|s| s.as_str().to_string()),
-        querify(m.get(4).map_or("", |s| s.as_str()))
-            .iter()
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect()
+        m.get(3).map(|s| s.as_str().trim_end_matches('/').to_string()).filter(|s| !s.is_empty()),
+        parse_params(m.get(4)),
     ))
 }
 
@@ -369,10 +793,13 @@ fn fmt_simple_url(scheme: &str, owner: &str, repo: &str, rev_or_ref: &Option<Str
 
 /// format:
 /// (github|gitlab|sourcehut):<owner>/<repo>(/<rev-or-ref>)?(\?<params>)?
+///
+/// GitLab owners may contain URL-encoded subgroups (`group%2Fsubgroup`) and
+/// SourceHut owners are prefixed with `~`. The `host` parameter selects a
+/// self-hosted instance.
 #[derive(Clone, PartialEq, Debug)]
 pub struct SimpleGitUrl {
     scheme: String,
-    domain: String,
     owner: String,
     repo: String,
     rev_or_ref: Option<String>,
@@ -380,36 +807,67 @@ pub struct SimpleGitUrl {
 }
 
 impl SimpleGitUrl {
-    pub fn parse(scheme: &str, domain: &str, url: &str) -> Result<Rc<dyn FlakeRef>> {
+    pub fn parse(scheme: &str, url: &str) -> Result<Rc<dyn FlakeRef>> {
         let (owner, repo, rev_or_ref, params) = parse_simple_url(url)?;
+        if rev_or_ref.is_some() && params.iter().any(|(k, _)| k == "ref" || k == "rev") {
+            bail!("'{scheme}:{url}' specifies a revision or ref both in the path and as a parameter");
+        }
         Ok(Rc::new(Self {
             scheme: scheme.to_string(),
-            domain: domain.to_string(),
             owner: owner,
             repo: repo,
             rev_or_ref: rev_or_ref,
             params: params
         }))
     }
+
+    fn host(&self) -> String {
+        find_param(&self.params, "host").unwrap_or(default_host(&self.flake_type()).to_string())
+    }
 }
 
 impl FlakeRef for SimpleGitUrl {
     fn flake_url(&self) -> String {
-        fmt_simple_url("github", &self.owner, &self.repo, &self.rev_or_ref, &self.params)
+        fmt_simple_url(&self.scheme, &self.owner, &self.repo, &self.rev_or_ref, &self.params)
+    }
+    fn canonical(&self) -> Rc<dyn FlakeRef> {
+        let mut copy = self.clone();
+        let host = self.host().to_lowercase();
+        if CASE_INSENSITIVE_HOSTS.contains(&host.as_str()) {
+            copy.owner = self.owner.to_lowercase();
+            copy.repo = self.repo.to_lowercase();
+        }
+        copy.repo = copy.repo.trim_end_matches(".git").to_string();
+        copy.rev_or_ref = self.arg("rev").or(self.arg("ref"));
+        let default = default_host(&self.flake_type());
+        copy.params = sorted_params(&self.params).into_iter()
+            .filter(|(k, v)| k != "ref" && k != "rev" && !(k == "host" && v.eq_ignore_ascii_case(default)))
+            .map(|(k, v)| match k == "host" {
+                true => (k, v.to_lowercase()),
+                false => (k, v),
+            })
+            .collect();
+        Rc::new(copy)
     }
     fn flake_type(&self) -> FlakeType {
-        FlakeType::GitHub
+        match self.scheme.as_str() {
+            "gitlab" => FlakeType::GitLab,
+            "sourcehut" => FlakeType::SourceHut,
+            _ => FlakeType::GitHub,
+        }
     }
     fn git_remote_url(&self) -> Option<String> {
         Some(format!(
-            "https://{domain}{owner}/{repo}.git",
-            domain=self.domain,
-            owner=self.owner,
+            "https://{host}/{owner}/{repo}{suffix}",
+            host=self.host(),
+            owner=self.owner.replace("%2F", "/").replace("%2f", "/"),
             repo=self.repo,
+            suffix=if self.scheme == "sourcehut" { "" } else { ".git" },
         ))
     }
     fn with_rev(&self, rev: &str) -> Rc<dyn FlakeRef> {
         let mut clone = self.clone();
+        clone.params.retain(|(k, _)| k != "rev" && k != "ref");
         clone.rev_or_ref = Some(rev.to_string());
         Rc::new(clone)
     }
@@ -417,8 +875,13 @@ impl FlakeRef for SimpleGitUrl {
         match arg {
             "owner" => Some(self.owner.to_string()),
             "repo" => Some(self.repo.to_string()),
+            "host" => Some(self.host()),
             "rev_or_ref" => self.rev_or_ref.clone(),
-            _ => None
+            "ref" => self.rev_or_ref.clone().filter(|r| !is_rev(r))
+                .or(find_param(&self.params, "ref")),
+            "rev" => self.rev_or_ref.clone().filter(|r| is_rev(r))
+                .or(find_param(&self.params, "rev")),
+            _ => find_param(&self.params, arg),
         }
     }
 }
@@ -427,7 +890,7 @@ impl FlakeRef for SimpleGitUrl {
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
-    use super::{FlakeRef, FlakeType};
+    use super::{FlakeRef, FlakeType, InputSpec};
 
     #[test]
     fn test_it_parses_flake_indirect() -> Result<()> {
@@ -516,6 +979,214 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_round_trips_all_flake_refs() -> Result<()> {
+        use FlakeType::*;
+        let rev = "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293";
+        type Case<'a> = (String, FlakeType, Vec<(&'a str, Option<&'a str>)>);
+        let cases: Vec<Case> = vec![
+            // indirect
+            ("nixpkgs".into(), Indirect, vec![("id", Some("nixpkgs")), ("ref", None)]),
+            ("nixpkgs/nixos-23.11".into(), Indirect, vec![("ref", Some("nixos-23.11"))]),
+            (format!("nixpkgs/{rev}"), Indirect, vec![("ref", None), ("rev", Some(rev))]),
+            ("flake:nixpkgs?dir=lib".into(), Indirect, vec![("dir", Some("lib"))]),
+            (format!("flake:nixpkgs/nixos-unstable/{rev}?dir=lib"), Indirect, vec![("ref", Some("nixos-unstable")), ("rev", Some(rev)), ("dir", Some("lib"))]),
+            // paths
+            ("path:/abs/path".into(), Path, vec![("path", Some("/abs/path"))]),
+            ("path:./rel?dir=sub&narHash=sha256-abc".into(), Path, vec![("dir", Some("sub")), ("narHash", Some("sha256-abc"))]),
+            (".".into(), Path, vec![("path", Some("."))]),
+            ("./foo".into(), Path, vec![("path", Some("./foo"))]),
+            ("/abs?dir=sub".into(), Path, vec![("path", Some("/abs")), ("dir", Some("sub"))]),
+            // git
+            ("git+https://example.com/repo.git?ref=main&rev=abc".into(), Git, vec![("ref", Some("main")), ("rev", Some("abc"))]),
+            ("git+http://example.com/repo".into(), Git, vec![]),
+            ("git+ssh://git@example.com:22/owner/repo.git".into(), Git, vec![]),
+            ("git+git://example.com/repo?dir=sub&submodules=1".into(), Git, vec![("dir", Some("sub")), ("submodules", Some("1"))]),
+            ("git+file:///home/user/repo".into(), Git, vec![]),
+            ("git+file:./relative".into(), Git, vec![]),
+            // mercurial
+            ("hg+https://example.com/repo?ref=default".into(), Mercurial, vec![("ref", Some("default"))]),
+            ("hg+ssh://example.com/repo".into(), Mercurial, vec![]),
+            ("hg+file:/share/repo".into(), Mercurial, vec![]),
+            // tarballs
+            ("tarball+https://example.com/foo.tar.gz".into(), Tarball, vec![("url", Some("https://example.com/foo.tar.gz"))]),
+            ("tarball+file:///tmp/foo.tar".into(), Tarball, vec![]),
+            ("https://example.com/foo-1.0.tar.xz?narHash=sha256-abc".into(), Tarball, vec![("narHash", Some("sha256-abc"))]),
+            ("http://example.com/foo.zip".into(), Tarball, vec![]),
+            ("file:///tmp/foo.tgz".into(), Tarball, vec![]),
+            // files
+            ("file+https://example.com/flake.nix".into(), File, vec![("url", Some("https://example.com/flake.nix"))]),
+            ("file+file:///tmp/foo".into(), File, vec![]),
+            ("https://example.com/download".into(), File, vec![]),
+            ("file:///tmp/foo".into(), File, vec![]),
+            // github
+            ("github:NixOS/nixpkgs".into(), GitHub, vec![("owner", Some("NixOS")), ("repo", Some("nixpkgs")), ("ref", None), ("rev", None)]),
+            ("github:NixOS/nixpkgs/nixos-23.11".into(), GitHub, vec![("ref", Some("nixos-23.11")), ("rev", None)]),
+            (format!("github:NixOS/nixpkgs/{rev}"), GitHub, vec![("ref", None), ("rev", Some(rev))]),
+            ("github:NixOS/nixpkgs?ref=nixos-23.11".into(), GitHub, vec![("ref", Some("nixos-23.11"))]),
+            (format!("github:NixOS/nixpkgs?rev={rev}"), GitHub, vec![("rev", Some(rev))]),
+            ("github:NixOS/nixpkgs?dir=lib&host=github.example.com".into(), GitHub, vec![("dir", Some("lib")), ("host", Some("github.example.com"))]),
+            ("github:NixOS/nixpkgs/refs/heads/nixos-23.11".into(), GitHub, vec![("ref", Some("refs/heads/nixos-23.11"))]),
+            // gitlab
+            ("gitlab:veloren/veloren".into(), GitLab, vec![("host", Some("gitlab.com"))]),
+            ("gitlab:veloren%2Fdev/rfcs/main".into(), GitLab, vec![("owner", Some("veloren%2Fdev")), ("repo", Some("rfcs")), ("ref", Some("main"))]),
+            ("gitlab:openldap/openldap?host=git.openldap.org".into(), GitLab, vec![("host", Some("git.openldap.org"))]),
+            // sourcehut
+            ("sourcehut:~misterio/nix-colors".into(), SourceHut, vec![("owner", Some("~misterio"))]),
+            ("sourcehut:~misterio/nix-colors/main?host=hg.sr.ht".into(), SourceHut, vec![("ref", Some("main")), ("host", Some("hg.sr.ht"))]),
+        ];
+        for (url, flake_type, args) in cases {
+            let flake_ref = super::parse(&url)?;
+            assert_eq!(flake_ref.flake_url(), url);
+            assert_eq!(flake_ref.flake_type(), flake_type, "{url}");
+            for (arg, value) in args {
+                assert_eq!(flake_ref.arg(arg).as_deref(), value, "{url} {arg}");
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_git_remote_urls() -> Result<()> {
+        let cases = [
+            ("git+ssh://git@example.com:22/owner/repo.git?ref=main", "ssh://git@example.com:22/owner/repo.git"),
+            ("git+git://example.com/repo", "git://example.com/repo"),
+            ("github:NixOS/nixpkgs/nixos-23.11", "https://github.com/NixOS/nixpkgs.git"),
+            ("github:corp/repo?host=github.example.com", "https://github.example.com/corp/repo.git"),
+            ("gitlab:veloren%2Fdev/rfcs", "https://gitlab.com/veloren/dev/rfcs.git"),
+            ("sourcehut:~misterio/nix-colors", "https://git.sr.ht/~misterio/nix-colors"),
+        ];
+        for (url, remote) in cases {
+            assert_eq!(super::parse(url)?.git_remote_url().as_deref(), Some(remote), "{url}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_invalid_flake_refs() {
+        for url in [
+            "unknown:foo",
+            "git+ftp://example.com/repo",
+            "hg+git://example.com/repo",
+            "tarball+ssh://example.com/foo.tar.gz",
+            "github:NixOS",
+            "github:NixOS/nixpkgs/main?ref=main",
+            "nixpkgs/a/b/c",
+            "-nixpkgs",
+        ] {
+            assert!(super::parse(url).is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_with_rev() -> Result<()> {
+        let rev = "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293";
+        let cases = [
+            ("github:NixOS/nixpkgs/nixos-23.11", format!("github:NixOS/nixpkgs/{rev}")),
+            ("github:NixOS/nixpkgs?ref=nixos-23.11&dir=lib", format!("github:NixOS/nixpkgs/{rev}?dir=lib")),
+            ("git+https://example.com/repo?rev=abc&ref=main", format!("git+https://example.com/repo?ref=main&rev={rev}")),
+            ("flake:nixpkgs/nixos-unstable", format!("flake:nixpkgs/nixos-unstable/{rev}")),
+            ("nixpkgs", format!("nixpkgs/{rev}")),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(super::parse(url)?.with_rev(rev).flake_url(), expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_urls() -> Result<()> {
+        let cases = [
+            ("github:Foo/Bar/", "github:foo/bar"),
+            ("github:foo/bar?ref=main", "github:foo/bar/main"),
+            ("github:foo/bar?host=github.com", "github:foo/bar"),
+            ("github:foo/bar?host=GitHub.com", "github:foo/bar"),
+            ("github:foo/bar?host=GitHub.Example.com", "github:foo/bar?host=github.example.com"),
+            ("gitlab:foo/bar?host=gitlab.example.com", "gitlab:foo/bar?host=gitlab.example.com"),
+            ("nixpkgs", "flake:nixpkgs"),
+            ("./project/", "path:./project"),
+            ("git+https://Example.com/repo?rev=abc&ref=main", "git+https://example.com/repo?ref=main&rev=abc"),
+            ("https://example.com/a.tar.gz?narHash=x&dir=lib", "tarball+https://example.com/a.tar.gz?dir=lib&narHash=x"),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(super::parse(url)?.canonical_url(), expected, "{url}");
+            assert_eq!(super::parse(expected)?.canonical_url(), expected, "{expected}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_equivalent_flake_refs() -> Result<()> {
+        let equivalent = |a: &str, b: &str| -> Result<bool> {
+            Ok(super::parse(a)?.equivalent(super::parse(b)?.as_ref()))
+        };
+        assert!(equivalent("github:Foo/bar", "github:foo/bar/")?);
+        assert!(equivalent("github:foo/bar", "git+https://github.com/foo/bar.git")?);
+        assert!(equivalent("github:foo/bar", "git+ssh://git@github.com/foo/bar")?);
+        assert!(equivalent("gitlab:foo/bar/main", "git+https://gitlab.com/foo/bar?ref=main")?);
+        assert!(equivalent("sourcehut:~foo/bar", "git+https://git.sr.ht/~foo/bar")?);
+        assert!(equivalent("github:foo/bar?dir=lib&ref=main", "github:foo/bar?ref=main&dir=lib/")?);
+        assert!(equivalent("flake:nixpkgs", "nixpkgs")?);
+        assert!(!equivalent("github:foo/bar", "github:foo/baz")?);
+        assert!(!equivalent("github:foo/bar", "github:foo/bar/main")?);
+        assert!(!equivalent("github:foo/bar", "github:foo/bar?dir=lib")?);
+        assert!(!equivalent("git+https://example.com/Repo", "git+https://example.com/repo")?);
+        assert!(!equivalent("github:foo/bar?host=github.example.com", "github:foo/bar")?);
+        assert!(equivalent("github:foo/bar?host=github.example.com", "git+https://github.example.com/foo/bar.git")?);
+        assert!(super::parse("github:foo/bar")?.same_source(super::parse("github:foo/bar/main")?.as_ref()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tarball_templates() -> Result<()> {
+        let template = "https://example.com/releases/{version}/foo-{version}.tar.gz";
+        let flake_ref = super::parse(template)?;
+        assert_eq!(flake_ref.with_rev("1.2.0").flake_url(), "https://example.com/releases/1.2.0/foo-1.2.0.tar.gz");
+        assert_eq!(super::template_version(template, "https://example.com/releases/1.2.0/foo-1.2.0.tar.gz"), Some("1.2.0".to_string()));
+        assert_eq!(super::template_version(template, "https://example.com/releases/1.2.0/foo-1.3.0.tar.gz"), None);
+        assert_eq!(super::template_version("https://example.com/foo.tar.gz", "https://example.com/foo.tar.gz"), None);
+        let plain = super::parse("https://example.com/foo.tar.gz")?;
+        assert_eq!(plain.with_rev("1.2.0").flake_url(), plain.flake_url());
+        assert_eq!(super::parse("hg+https://example.com/repo?ref=default")?.hg_remote_url(), Some("https://example.com/repo".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_input_spec() -> Result<()> {
+        let cases = [
+            (r#"{"type": "github", "owner": "NixOS", "repo": "nixpkgs", "ref": "nixos-unstable"}"#, "github:NixOS/nixpkgs/nixos-unstable"),
+            (r#"{"type": "github", "owner": "chadac", "repo": "nixspace", "dir": "lib"}"#, "github:chadac/nixspace?dir=lib"),
+            (r#"{"type": "git", "url": "https://example.com/repo", "ref": "main"}"#, "git+https://example.com/repo?ref=main"),
+            (r#"{"type": "path", "path": "./project"}"#, "path:./project"),
+            (r#"{"type": "tarball", "url": "https://example.com/a.tar.gz"}"#, "tarball+https://example.com/a.tar.gz"),
+            (r#"{"type": "indirect", "id": "nixpkgs"}"#, "flake:nixpkgs"),
+        ];
+        for (spec, expected) in cases {
+            let spec: InputSpec = serde_json::from_str(spec)?;
+            assert_eq!(super::from_input_spec(&spec)?.flake_url(), expected);
+        }
+        let spec: InputSpec = serde_json::from_str(r#"{"type": "github", "owner": "chadac"}"#)?;
+        assert!(super::from_input_spec(&spec).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_git_remote() -> Result<()> {
+        let cases = [
+            ("https://github.com/chadac/nixspace.git", None, "github:chadac/nixspace"),
+            ("https://github.com/chadac/nixspace", Some("sub/dir"), "github:chadac/nixspace?dir=sub/dir"),
+            ("https://git.example.com/chadac/nixspace.git", None, "git+https://git.example.com/chadac/nixspace.git"),
+            ("ssh://git@github.com/chadac/nixspace.git", None, "git+ssh://git@github.com/chadac/nixspace.git"),
+            ("git@github.com:chadac/nixspace.git", None, "git+ssh://git@github.com/chadac/nixspace.git"),
+            ("/share/repo", Some(""), "git+file:/share/repo"),
+        ];
+        for (remote, dir, expected) in cases {
+            assert_eq!(super::from_git_remote(remote, dir)?.flake_url(), expected);
+        }
+        assert!(super::from_git_remote("nonsense", None).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_it_parses_github_url() -> Result<()> {
         let url1 = "github:chadac/dotfiles/nix-config";
@@ -528,4 +1199,155 @@ mod tests {
         assert_eq!(ref1.arg("rev_or_ref"), Some("nix-config".to_string()));
         Ok(())
     }
+
+    #[test]
+    fn test_input_spec() -> Result<()> {
+        let rev = "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293";
+        let cases = [
+            (format!("github:NixOS/nixpkgs/{rev}"), format!(r#"{{"type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "{rev}"}}"#)),
+            ("github:chadac/nixspace/main?dir=lib".to_string(), r#"{"type": "github", "owner": "chadac", "repo": "nixspace", "ref": "main", "dir": "lib"}"#.to_string()),
+            ("gitlab:chadac/nixspace?host=git.example.com".to_string(), r#"{"type": "gitlab", "owner": "chadac", "repo": "nixspace", "host": "git.example.com"}"#.to_string()),
+            ("git+https://example.com/repo?ref=main&submodules=1".to_string(), r#"{"type": "git", "url": "https://example.com/repo?submodules=1", "ref": "main"}"#.to_string()),
+            ("hg+https://example.com/repo?rev=abc".to_string(), r#"{"type": "hg", "url": "https://example.com/repo", "rev": "abc"}"#.to_string()),
+            ("https://example.com/a.tar.gz?narHash=sha256-abc".to_string(), r#"{"type": "tarball", "url": "https://example.com/a.tar.gz", "narHash": "sha256-abc"}"#.to_string()),
+            ("./project?lastModified=1700000000".to_string(), r#"{"type": "path", "path": "./project", "lastModified": 1700000000}"#.to_string()),
+            ("nixpkgs/nixos-unstable".to_string(), r#"{"type": "indirect", "id": "nixpkgs", "ref": "nixos-unstable"}"#.to_string()),
+        ];
+        for (url, spec) in cases {
+            let expected: InputSpec = serde_json::from_str(&spec)?;
+            assert_eq!(super::parse(&url)?.input_spec(), expected, "{url}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_infer_name() -> Result<()> {
+        let cases = [
+            ("github:chadac/nixspace/main", Some("nixspace")),
+            ("git+ssh://git@example.com/chadac/nixspace.git?ref=main", Some("nixspace")),
+            ("https://example.com/releases/nixspace.tar.gz", Some("nixspace")),
+            ("path:/home/chadac/nixspace/", Some("nixspace")),
+            ("flake:nixpkgs", Some("nixpkgs")),
+            (".", None),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(super::parse(url)?.infer_name().as_deref(), expected, "{url}");
+        }
+        Ok(())
+    }
+
+    mod properties {
+        use proptest::prelude::*;
+        use super::super::{from_input_spec, parse, FlakeRef};
+        use crate::lockfile::{FlakeType, InputSpec};
+
+        fn spec(flake_type: FlakeType) -> InputSpec {
+            InputSpec {
+                flake_type,
+                nar_hash: None,
+                id: None,
+                path: None,
+                url: None,
+                owner: None,
+                repo: None,
+                host: None,
+                dir: None,
+                rev: None,
+                git_ref: None,
+                rev_count: None,
+                last_modified: None,
+            }
+        }
+
+        fn name() -> impl Strategy<Value = String> {
+            "[a-z][a-z0-9_-]{0,8}"
+        }
+
+        fn rev() -> impl Strategy<Value = Option<String>> {
+            proptest::option::of("[0-9a-f]{40}")
+        }
+
+        fn git_ref() -> impl Strategy<Value = Option<String>> {
+            proptest::option::of("[a-z][a-z0-9.-]{0,8}(/[a-z0-9]{1,4})?")
+        }
+
+        fn server_url(suffix: &'static str) -> impl Strategy<Value = String> {
+            ("(http|https|ssh|file)", "[a-z]{1,8}\\.[a-z]{2,3}", "(/[a-z0-9_-]{1,8}){1,3}")
+                .prop_map(move |(scheme, host, path)| match scheme.as_str() {
+                    "file" => format!("file://{path}{suffix}"),
+                    _ => format!("{scheme}://{host}{path}{suffix}"),
+                })
+        }
+
+        prop_compose! {
+            fn simple_git()(
+                flake_type in prop_oneof![Just(FlakeType::GitHub), Just(FlakeType::GitLab), Just(FlakeType::SourceHut)],
+                owner in name(), repo in name(), git_ref in git_ref(), rev in rev(),
+                host in proptest::option::of("git\\.[a-z]{1,8}\\.com"),
+            ) -> InputSpec {
+                InputSpec { owner: Some(owner), repo: Some(repo), git_ref, rev, host, ..spec(flake_type) }
+            }
+        }
+
+        prop_compose! {
+            fn repository()(
+                flake_type in prop_oneof![Just(FlakeType::Git), Just(FlakeType::Mercurial)],
+                url in server_url(""), git_ref in git_ref(), rev in rev(),
+                rev_count in proptest::option::of(0..100000i64),
+            ) -> InputSpec {
+                InputSpec { url: Some(url), git_ref, rev, rev_count, ..spec(flake_type) }
+            }
+        }
+
+        prop_compose! {
+            fn archive()(
+                flake_type in prop_oneof![Just(FlakeType::Tarball), Just(FlakeType::File)],
+                url in server_url(".tar.gz"),
+            ) -> InputSpec {
+                InputSpec { url: Some(url.replace("ssh:", "https:")), ..spec(flake_type) }
+            }
+        }
+
+        prop_compose! {
+            fn path()(path in "(\\.|/[a-z]{1,8})(/[a-z0-9_-]{1,8}){0,3}") -> InputSpec {
+                InputSpec { path: Some(path), ..spec(FlakeType::Path) }
+            }
+        }
+
+        prop_compose! {
+            fn indirect()(id in name(), git_ref in git_ref(), rev in rev()) -> InputSpec {
+                InputSpec { id: Some(id), git_ref: git_ref.map(|r| r.replace('/', "-")), rev, ..spec(FlakeType::Indirect) }
+            }
+        }
+
+        prop_compose! {
+            fn input_spec()(
+                base in prop_oneof![simple_git(), repository(), archive(), path(), indirect()],
+                dir in proptest::option::of("[a-z]{1,6}(/[a-z]{1,6})?"),
+                nar_hash in proptest::option::of("sha256-[A-Za-z0-9]{8}"),
+                last_modified in proptest::option::of(0..2000000000i64),
+            ) -> InputSpec {
+                InputSpec { dir, nar_hash, last_modified, ..base }
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn input_spec_round_trips(spec in input_spec()) {
+                let flake_ref = from_input_spec(&spec).unwrap();
+                prop_assert_eq!(flake_ref.input_spec(), spec.clone());
+                prop_assert_eq!(parse(&flake_ref.flake_url()).unwrap().input_spec(), spec.clone());
+                let json = serde_json::to_string(&spec).unwrap();
+                prop_assert_eq!(serde_json::from_str::<InputSpec>(&json).unwrap(), spec);
+            }
+
+            #[test]
+            fn flake_url_round_trips(spec in input_spec()) {
+                let flake_ref = from_input_spec(&spec).unwrap();
+                let converted = from_input_spec(&flake_ref.input_spec()).unwrap();
+                prop_assert!(converted.equivalent(flake_ref.as_ref()));
+                prop_assert_eq!(converted.canonical_url(), flake_ref.canonical_url());
+            }
+        }
+    }
 }
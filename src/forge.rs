@@ -0,0 +1,233 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Serialize, Deserialize};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+/// Which forge review requests are opened with, set by the `[forge]`
+/// section of nixspace.toml.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum ForgeConfig {
+    /// only push the branch; the request is opened by hand
+    #[default]
+    #[serde(rename = "git")]
+    Git,
+    /// post the request as JSON to an HTTP endpoint
+    #[serde(rename = "http")]
+    Http {
+        url: String,
+        /// environment variable holding a bearer token for the endpoint
+        token_env: Option<String>,
+    },
+}
+
+impl ForgeConfig {
+    pub fn is_default(&self) -> bool {
+        *self == ForgeConfig::default()
+    }
+}
+
+/// A request to merge a pushed branch, such as a pull request.
+#[derive(Serialize, Debug)]
+pub struct ChangeRequest {
+    pub title: String,
+    pub description: String,
+    pub source_branch: String,
+    pub target_branch: String,
+}
+
+/// A code forge that branches can be proposed for review on.
+pub trait Forge {
+    /// Opens a review request for a branch that has already been pushed.
+    /// Returns where the request can be found.
+    fn open(&self, request: &ChangeRequest) -> Result<String>;
+}
+
+pub fn from_config(config: &ForgeConfig) -> Box<dyn Forge> {
+    match config {
+        ForgeConfig::Git => Box::new(GitForge {}),
+        ForgeConfig::Http { url, token_env } => Box::new(HttpForge {
+            url: url.to_string(),
+            token_env: token_env.clone(),
+        }),
+    }
+}
+
+/// Works with any git remote by leaving the request to the user.
+pub struct GitForge {}
+
+impl Forge for GitForge {
+    fn open(&self, request: &ChangeRequest) -> Result<String> {
+        Ok(format!(
+            "pushed '{}'; open a request to merge it into '{}' on your forge",
+            request.source_branch, request.target_branch,
+        ))
+    }
+}
+
+/// Posts requests as JSON to an HTTP endpoint, such as a bot that opens
+/// pull requests. The endpoint responds with the `url` of the request.
+pub struct HttpForge {
+    pub url: String,
+    pub token_env: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HttpForgeResponse {
+    #[serde(alias = "web_url", alias = "html_url")]
+    url: String,
+}
+
+impl Forge for HttpForge {
+    fn open(&self, request: &ChangeRequest) -> Result<String> {
+        let token = match &self.token_env {
+            Some(var) => Some(std::env::var(var).with_context(|| anyhow!("forge token variable '{var}' is not set"))?),
+            None => None,
+        };
+        let body = post_json(&self.url, token.as_deref(), &serde_json::to_string(request)?)?;
+        let response: HttpForgeResponse = serde_json::from_str(&body)
+            .with_context(|| anyhow!("unexpected response from forge: {body}"))?;
+        Ok(response.url)
+    }
+}
+
+/// True if the host of an `http://` authority is this machine, so that
+/// nothing sent to it crosses the network in the clear.
+fn is_loopback(authority: &str) -> bool {
+    let host = match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap(),
+        None => authority.split(':').next().unwrap(),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Sends a JSON body with a minimal HTTP/1.0 client and returns the body of
+/// the response. Only plain `http://` URLs are supported, so a token is only
+/// sent to loopback hosts, such as a local proxy in front of the forge.
+fn post_json(url: &str, token: Option<&str>, body: &str) -> Result<String> {
+    let rest = url.strip_prefix("http://")
+        .with_context(|| anyhow!("unsupported forge URL '{url}'; only http:// is supported"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if token.is_some() && !is_loopback(authority) {
+        bail!("refusing to send the forge token to '{authority}' over plain http; only loopback hosts are supported");
+    }
+    let address = match authority.contains(':') {
+        true => authority.to_string(),
+        false => format!("{authority}:80"),
+    };
+    let mut stream = TcpStream::connect(&address)
+        .with_context(|| anyhow!("could not connect to forge at '{url}'"))?;
+    let mut request = format!(
+        "POST {path} HTTP/1.0\r\nHost: {authority}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+        body.len(),
+    );
+    if let Some(token) = token {
+        request.push_str(&format!("Authorization: Bearer {token}\r\n"));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").context("malformed HTTP response from forge")?;
+    let status: u16 = head.split_whitespace().nth(1).and_then(|s| s.parse().ok())
+        .context("malformed HTTP response from forge")?;
+    if !(200..300).contains(&status) {
+        bail!("forge responded with HTTP {status}: {}", body.trim());
+    }
+    Ok(body.to_string())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    /// Stands in for a forge: answers a single request with `response` and
+    /// returns the raw request it received.
+    pub fn serve_once(status: &str, response: &str) -> Result<(String, JoinHandle<String>)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/requests", listener.local_addr()?);
+        let (status, response) = (status.to_string(), response.to_string());
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0; 4096];
+            // read the headers, then as much of the body as they announce
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                received.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&received).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length: usize = head.lines()
+                        .find_map(|l| l.strip_prefix("Content-Length: "))
+                        .map(|l| l.trim().parse().unwrap())
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+                if n == 0 {
+                    break;
+                }
+            }
+            write!(stream, "HTTP/1.0 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{response}", response.len()).unwrap();
+            String::from_utf8(received).unwrap()
+        });
+        Ok((url, handle))
+    }
+
+    fn request() -> ChangeRequest {
+        ChangeRequest {
+            title: "update 2 projects in prod".to_string(),
+            description: "* a: a1a1a1a → a2a2a2a".to_string(),
+            source_branch: "ns/update-prod-2024-01-01".to_string(),
+            target_branch: "main".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_http_forge() -> Result<()> {
+        let (url, server) = serve_once("201 Created", r#"{ "web_url": "http://forge.test/requests/7" }"#)?;
+        let forge = HttpForge { url, token_env: None };
+        assert_eq!(forge.open(&request())?, "http://forge.test/requests/7");
+        let received = server.join().unwrap();
+        assert!(received.starts_with("POST /requests HTTP/1.0\r\n"));
+        let body: serde_json::Value = serde_json::from_str(received.split_once("\r\n\r\n").unwrap().1)?;
+        assert_eq!(body["source_branch"], "ns/update-prod-2024-01-01");
+        assert_eq!(body["description"], "* a: a1a1a1a → a2a2a2a");
+
+        let (url, server) = serve_once("422 Unprocessable Entity", r#"{ "error": "branch not found" }"#)?;
+        let forge = HttpForge { url, token_env: None };
+        let err = forge.open(&request()).err().unwrap().to_string();
+        assert!(err.contains("HTTP 422"), "{err}");
+        server.join().unwrap();
+
+        assert!(HttpForge { url: "https://forge.test".to_string(), token_env: None }.open(&request()).is_err());
+
+        std::env::set_var("NS_TEST_FORGE_TOKEN", "secret");
+        let (url, server) = serve_once("201 Created", r#"{ "url": "http://forge.test/requests/8" }"#)?;
+        let forge = HttpForge { url, token_env: Some("NS_TEST_FORGE_TOKEN".to_string()) };
+        assert_eq!(forge.open(&request())?, "http://forge.test/requests/8");
+        assert!(server.join().unwrap().contains("Authorization: Bearer secret\r\n"));
+        let forge = HttpForge { url: "http://forge.test/requests".to_string(), token_env: Some("NS_TEST_FORGE_TOKEN".to_string()) };
+        let err = forge.open(&request()).err().unwrap().to_string();
+        assert!(err.contains("refusing to send the forge token"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_loopback() {
+        assert!(is_loopback("localhost:8080"));
+        assert!(is_loopback("127.0.0.1"));
+        assert!(is_loopback("[::1]:8080"));
+        assert!(!is_loopback("forge-bot.internal"));
+        assert!(!is_loopback("10.0.0.1:80"));
+    }
+}
@@ -0,0 +1,175 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use super::cli::Git;
+use super::flake::FlakeRef;
+use super::lockfile::LockFile;
+
+/// A project discovered from an existing lockfile or local checkout that
+/// has not been registered with the workspace yet.
+pub struct ProjectImport {
+    pub name: String,
+    pub flake_ref: Rc<dyn FlakeRef>,
+    pub path: Option<PathBuf>,
+    /// revision to lock the project at, rather than following its strategy
+    pub rev: Option<String>,
+    /// if true, the project is already checked out at `path`
+    pub editable: bool,
+}
+
+/// How to handle an imported project whose name is already registered.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Debug)]
+pub enum OnConflict {
+    /// abort the import
+    Fail,
+    /// skip the conflicting project
+    Skip,
+    /// register the project under a new name with a numeric suffix
+    Rename,
+}
+
+/// Inspects a local checkout inside the workspace, deriving its flake
+/// reference from the checkout's git remote.
+pub fn inspect_checkout(root: &Path, dir: &Path, name: &Option<String>) -> Result<ProjectImport> {
+    let dir = std::fs::canonicalize(dir)
+        .with_context(|| anyhow!("could not find directory '{}'", dir.display()))?;
+    if !dir.join("flake.nix").exists() {
+        bail!("'{}' is not a flake; no flake.nix found", dir.display());
+    }
+    let root = std::fs::canonicalize(root)?;
+    if !dir.starts_with(&root) {
+        bail!("'{}' is not inside the workspace at '{}'", dir.display(), root.display());
+    }
+    // projects in subdirectories of a repository share its checkout
+    let checkout = std::fs::canonicalize(Git::toplevel(&dir)?)?;
+    let path = checkout.strip_prefix(&root)
+        .with_context(|| anyhow!("the repository at '{}' is not inside the workspace at '{}'", checkout.display(), root.display()))?
+        .to_path_buf();
+
+    let subdir = Git::prefix(&dir)?;
+    let flake_ref = crate::flake::from_git_remote(&Git::remote_url(&dir)?, Some(&subdir))?;
+    let name = match name {
+        Some(n) => n.to_string(),
+        None if !subdir.is_empty() => subdir.rsplit('/').next().unwrap().to_string(),
+        None => flake_ref.infer_name()
+            .or(dir.file_name().map(|n| n.to_string_lossy().to_string()))
+            .context("could not infer project name!")?,
+    };
+    Ok(ProjectImport {
+        name,
+        flake_ref,
+        path: Some(path),
+        rev: None,
+        editable: true,
+    })
+}
+
+/// Reads every direct flake input of a `flake.lock` as a project, locked to
+/// the same revision.
+pub fn from_flake_lock(path: &Path) -> Result<Vec<ProjectImport>> {
+    let lock = LockFile::read(path)
+        .with_context(|| anyhow!("could not read lockfile '{}'", path.display()))?;
+    let mut imports = Vec::new();
+    for (name, node) in lock.root_inputs()? {
+        if !lock.is_flake(&node) {
+            log::info!("skipping input '{name}'; not a flake");
+            continue;
+        }
+        let original = match lock.get_original_spec(&node) {
+            Some(spec) => spec,
+            None => {
+                log::warn!("skipping input '{name}'; missing original spec");
+                continue;
+            },
+        };
+        let flake_ref = match crate::flake::from_input_spec(&original) {
+            Ok(f) => f,
+            Err(e) => {
+                log::warn!("skipping input '{name}': {e}");
+                continue;
+            },
+        };
+        imports.push(ProjectImport {
+            path: Some(PathBuf::from(&name)),
+            rev: lock.get_input_spec(&node).and_then(|spec| spec.rev),
+            name,
+            flake_ref,
+            editable: false,
+        });
+    }
+    Ok(imports)
+}
+
+/// Finds every flake with a git remote nested under `dir`.
+///
+/// The workspace flake itself, hidden directories and symlinks are skipped.
+pub fn scan(root: &Path, dir: &Path) -> Result<Vec<ProjectImport>> {
+    let root = std::fs::canonicalize(root)?;
+    let mut queue = vec![std::fs::canonicalize(dir)?];
+    let mut imports = Vec::new();
+    while let Some(next) = queue.pop() {
+        if next != root && next.join("flake.nix").exists() {
+            match inspect_checkout(&root, &next, &None) {
+                Ok(import) => imports.push(import),
+                Err(e) => log::warn!("skipping '{}': {e}", next.display()),
+            }
+        }
+        for entry in std::fs::read_dir(&next)? {
+            let entry = entry?;
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if entry.file_type()?.is_dir() && !hidden {
+                queue.push(entry.path());
+            }
+        }
+    }
+    imports.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(imports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_from_flake_lock() -> Result<()> {
+        let tmp = TempDir::new("import")?;
+        let path = tmp.path().join("flake.lock");
+        std::fs::write(&path, r#"{
+            "nodes": {
+                "root": { "inputs": { "nixpkgs": "nixpkgs", "shared": "shared", "src": "src", "utils": ["shared", "utils"] } },
+                "nixpkgs": {
+                    "locked": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "a3a3dda3bacf61e8a39258a0ed9c924eeca8e293" },
+                    "original": { "type": "indirect", "id": "nixpkgs" }
+                },
+                "shared": {
+                    "inputs": { "utils": "utils" },
+                    "locked": { "type": "git", "url": "https://example.com/shared", "ref": "main", "rev": "b4b4" },
+                    "original": { "type": "git", "url": "https://example.com/shared", "ref": "main" }
+                },
+                "utils": {
+                    "locked": { "type": "github", "owner": "numtide", "repo": "flake-utils", "rev": "c5c5" },
+                    "original": { "type": "github", "owner": "numtide", "repo": "flake-utils" }
+                },
+                "src": {
+                    "flake": false,
+                    "locked": { "type": "tarball", "url": "https://example.com/src.tar.gz" },
+                    "original": { "type": "tarball", "url": "https://example.com/src.tar.gz" }
+                }
+            },
+            "root": "root",
+            "version": 7
+        }"#)?;
+        let imports = from_flake_lock(&path)?;
+        let summary: Vec<_> = imports.iter()
+            .map(|i| (i.name.as_str(), i.flake_ref.flake_url(), i.rev.clone()))
+            .collect();
+        assert_eq!(summary, vec![
+            ("nixpkgs", "flake:nixpkgs".to_string(), Some("a3a3dda3bacf61e8a39258a0ed9c924eeca8e293".to_string())),
+            ("shared", "git+https://example.com/shared?ref=main".to_string(), Some("b4b4".to_string())),
+        ]);
+        assert_eq!(imports[0].path, Some(PathBuf::from("nixpkgs")));
+        Ok(())
+    }
+}
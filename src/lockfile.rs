@@ -1,6 +1,6 @@
 use serde::{Serialize, Deserialize};
 use anyhow::{anyhow, Context, Result};
-use std::collections::{HashSet, BTreeMap};
+use std::collections::{HashSet, BTreeMap, BTreeSet, VecDeque};
 use std::path::Path;
 use std::rc::Rc;
 
@@ -16,7 +16,7 @@ pub struct LockFile {
     version: i32,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 #[serde(untagged)]
 enum InputRef {
     Direct(String),
@@ -50,7 +50,7 @@ impl InputRef {
     }
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 struct LockedRef {
     #[serde(skip_serializing_if = "Option::is_none")]
     flake: Option<bool>,
@@ -68,7 +68,7 @@ pub enum FlakeType {
     Path,
     #[serde(rename = "git")]
     Git,
-    #[serde(rename = "mercurial")]
+    #[serde(rename = "hg", alias = "mercurial")]
     Mercurial,
     #[serde(rename = "tarball")]
     Tarball,
@@ -80,11 +80,11 @@ pub enum FlakeType {
     GitLab,
     #[serde(rename = "sourcehut")]
     SourceHut,
-    #[serde(rename = "flake")]
+    #[serde(rename = "indirect", alias = "flake")]
     Indirect,
 }
 
-#[derive(Clone, Serialize, Deserialize, Debug)]
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
 pub struct InputSpec {
     #[serde(rename = "type")]
     pub flake_type: FlakeType,
@@ -92,12 +92,18 @@ pub struct InputSpec {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub nar_hash: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub owner: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub repo: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub dir: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rev: Option<String>,
@@ -122,8 +128,16 @@ impl LockedRef {
         }
     }
 
+    /// Generates a root node that directly references every node given.
     fn root(nodes: &Nodes) -> Self {
-        todo!()
+        LockedRef {
+            flake: None,
+            locked: None,
+            original: None,
+            inputs: Some(BTreeMap::from_iter(
+                nodes.keys().map(|n| (n.to_string(), InputRef::Direct(n.to_string())))
+            )),
+        }
     }
 
     /// Generates a new LockedRef from an inputspec
@@ -201,7 +215,7 @@ impl LockFile {
     fn from_nodes(nodes: Nodes) -> Self {
         let mut new_nodes: Nodes = nodes
             .into_iter()
-            .filter(|(name, _)| name == "root")
+            .filter(|(name, _)| name != "root")
             .collect();
         let root = LockedRef::root(&new_nodes);
         new_nodes.insert("root".to_string(), root);
@@ -255,6 +269,28 @@ impl LockFile {
         self.nodes.get(name).map(|r| r.locked.clone()).flatten()
     }
 
+    pub fn get_original_spec(&self, name: &str) -> Option<InputSpec> {
+        self.nodes.get(name).and_then(|r| r.original.clone())
+    }
+
+    /// Returns true if the node is a flake, rather than a `flake = false` input.
+    pub fn is_flake(&self, name: &str) -> bool {
+        self.nodes.get(name).map(|r| r.flake.unwrap_or(true)).unwrap_or(false)
+    }
+
+    /// Maps the name of each direct input of the root node to its node name.
+    /// Inputs that follow another input are skipped.
+    pub fn root_inputs(&self) -> Result<BTreeMap<String, String>> {
+        let root = self.nodes.get(&self.root)
+            .context("lockfile is missing root node! improperly formatted?")?;
+        Ok(root.inputs.iter().flatten()
+            .filter_map(|(name, input_ref)| match input_ref {
+                InputRef::Direct(node) => Some((name.to_string(), node.to_string())),
+                InputRef::Path(_) => None,
+            })
+            .collect())
+    }
+
     /// Resolves the paths that Nix flakes use.
     ///
     /// Copies a bit of the callFlake pattern included in the core Nix repo just
@@ -319,9 +355,14 @@ impl LockFile {
     ///
     /// Useful for cleaning up the lockfile after updates.
     fn closure(&self) -> Result<HashSet<String>> {
-        let mut queue = Vec::from(&[ self.root.to_string() ]);
+        self.closure_from(&self.root)
+    }
+
+    /// Grabs all nodes in the lockfile that are reachable from a node.
+    fn closure_from(&self, start: &str) -> Result<HashSet<String>> {
+        let mut queue = Vec::from(&[ start.to_string() ]);
         let mut visited = HashSet::new();
-        visited.insert(self.root.to_string());
+        visited.insert(start.to_string());
 
         while !queue.is_empty() {
             let node_name = queue.pop().unwrap();
@@ -341,6 +382,62 @@ impl LockFile {
         Ok(visited)
     }
 
+    /// Returns the projects (inputs of the root node) that depend on any of
+    /// the given projects, either directly or transitively.
+    pub fn consumers(&self, names: &BTreeSet<String>) -> Result<BTreeSet<String>> {
+        let root = self.nodes.get(&self.root)
+            .context("lockfile is missing root node! improperly formatted?")?;
+        let mut consumers = BTreeSet::new();
+        for (name, input_ref) in root.inputs.iter().flatten() {
+            let node = self.resolve_input(input_ref);
+            let closure = self.closure_from(&node)?;
+            if names.iter().any(|n| *n != node && closure.contains(n)) {
+                consumers.insert(name.to_string());
+            }
+        }
+        Ok(consumers)
+    }
+
+    /// Lists every node reachable from the root along with the shortest path
+    /// of input names leading to it, e.g. `["app", "utils", "nixpkgs"]`.
+    /// Inputs that follow another input are not listed separately.
+    pub fn input_paths(&self) -> Vec<(Vec<String>, String)> {
+        let mut paths = Vec::new();
+        let mut seen = HashSet::from([self.root.to_string()]);
+        let mut queue = VecDeque::from([(Vec::new(), self.root.to_string())]);
+        while let Some((path, name)) = queue.pop_front() {
+            let inputs = self.nodes.get(&name).and_then(|n| n.inputs.as_ref());
+            for (input, input_ref) in inputs.into_iter().flatten() {
+                if let InputRef::Direct(node) = input_ref {
+                    if seen.insert(node.to_string()) {
+                        let mut input_path = path.clone();
+                        input_path.push(input.to_string());
+                        paths.push((input_path.clone(), node.to_string()));
+                        queue.push_back((input_path, node.to_string()));
+                    }
+                }
+            }
+        }
+        paths
+    }
+
+    /// Returns the nodes that are missing, unexpected or locked differently
+    /// compared to `expected`. `original` entries only record how an input
+    /// was written and are not compared.
+    pub fn mismatched_nodes(&self, expected: &LockFile) -> Vec<String> {
+        let same = |a: &LockedRef, b: &LockedRef| a.flake == b.flake && a.locked == b.locked && a.inputs == b.inputs;
+        let mut names: Vec<&String> = self.nodes.keys().chain(expected.nodes.keys()).collect();
+        names.sort();
+        names.dedup();
+        names.into_iter()
+            .filter(|name| match (self.nodes.get(*name), expected.nodes.get(*name)) {
+                (Some(a), Some(b)) => !same(a, b),
+                _ => true,
+            })
+            .map(|name| name.to_string())
+            .collect()
+    }
+
     /// Remove all nodes from the lockfile that are not attached to the root.
     pub fn trim(&mut self) -> Result<()> {
         let keep = self.closure()?;
@@ -357,6 +454,10 @@ impl InputSpec {
     pub fn from_flake_ref(flake_ref: Rc<dyn FlakeRef>) -> Self {
         flake_ref.input_spec()
     }
+
+    pub fn to_flake_ref(&self) -> Result<Rc<dyn FlakeRef>> {
+        crate::flake::from_input_spec(self)
+    }
 }
 
 
@@ -368,4 +469,70 @@ mod tests {
     fn add_succeeds() -> Result<()> {
         Ok(())
     }
+
+    #[test]
+    fn test_consumers() -> Result<()> {
+        let lockfile: LockFile = serde_json::from_str(r#"{
+            "nodes": {
+                "root": { "inputs": { "a": "a", "b": "b", "c": "c", "d": "d" } },
+                "a": { "inputs": { "b": "b", "nixpkgs": "a_nixpkgs" } },
+                "a_nixpkgs": { "inputs": {} },
+                "b": { "inputs": {} },
+                "c": { "inputs": { "x": "c_x" } },
+                "c_x": { "inputs": { "b": ["a", "b"] } },
+                "d": { "inputs": { "a": "a", "nixpkgs": ["a", "nixpkgs"] } }
+            },
+            "root": "root",
+            "version": 7
+        }"#)?;
+        let names = |ns: &[&str]| BTreeSet::from_iter(ns.iter().map(|n| n.to_string()));
+        assert_eq!(lockfile.consumers(&names(&["b"]))?, names(&["a", "c", "d"]));
+        assert_eq!(lockfile.consumers(&names(&["a"]))?, names(&["d"]));
+        assert_eq!(lockfile.consumers(&names(&["a", "b"]))?, names(&["a", "c", "d"]));
+        assert_eq!(lockfile.consumers(&names(&["d"]))?, names(&[]));
+
+        let paths: Vec<String> = lockfile.input_paths().into_iter().map(|(p, n)| format!("{}={n}", p.join("/"))).collect();
+        assert_eq!(paths, vec!["a=a", "b=b", "c=c", "d=d", "a/nixpkgs=a_nixpkgs", "c/x=c_x"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mismatched_nodes() -> Result<()> {
+        let metadata = |name: &str, locks: &str| -> Result<super::super::cli::FlakeMetadata> {
+            let spec = format!(r#"{{ "type": "github", "owner": "chadac", "repo": "{name}", "rev": "{}" }}"#, "1".repeat(40));
+            Ok(serde_json::from_str(&format!(r#"{{
+                "description": null,
+                "lastModified": 0,
+                "locked": {spec},
+                "original": {{ "type": "github", "owner": "chadac", "repo": "{name}" }},
+                "originalUrl": "github:chadac/{name}",
+                "path": "/nix/store/{name}",
+                "resolved": {spec},
+                "resolvedUrl": "github:chadac/{name}",
+                "revision": "{}",
+                "url": "github:chadac/{name}",
+                "locks": {locks}
+            }}"#, "1".repeat(40)))?)
+        };
+        let a = metadata("a", r#"{
+            "nodes": {
+                "root": { "inputs": { "b": "b", "nixpkgs": "nixpkgs" } },
+                "b": { "locked": { "type": "github", "owner": "chadac", "repo": "b", "rev": "old" } },
+                "nixpkgs": { "locked": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "abc" } }
+            },
+            "root": "root",
+            "version": 7
+        }"#)?;
+        let b = metadata("b", r#"{ "nodes": { "root": {} }, "root": "root", "version": 7 }"#)?;
+        let merged = LockFile::from_metadata(BTreeMap::from([("a".to_string(), a), ("b".to_string(), b)]))?;
+        assert!(merged.nodes.contains_key("a_nixpkgs"));
+        assert!(merged.mismatched_nodes(&merged).is_empty());
+
+        let mut tampered = merged.clone();
+        tampered.nodes.get_mut("a_nixpkgs").unwrap().locked.as_mut().unwrap().rev = Some("evil".to_string());
+        tampered.nodes.get_mut("b").unwrap().original = None;
+        tampered.nodes.insert("extra".to_string(), LockedRef::empty());
+        assert_eq!(tampered.mismatched_nodes(&merged), vec!["a_nixpkgs", "extra"]);
+        Ok(())
+    }
 }
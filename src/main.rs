@@ -8,16 +8,25 @@ mod lockfile;
 mod flake;
 mod cli;
 mod util;
+mod selector;
+mod import;
+mod registry;
+mod changelog;
+mod forge;
+mod policy;
 
-use crate::config::Config;
+use crate::config::{Config, SyncMode, UpdateStrategy};
 use crate::cli::{CliCommand, Git, Nix};
-use crate::workspace::{ProjectRef, Workspace};
+use crate::workspace::{ProjectRef, SyncOutcome, Workspace};
 use crate::flake::FlakeRef;
-use crate::lockfile::InputSpec;
+use crate::selector::Selector;
+use crate::import::OnConflict;
 
 use anyhow::{anyhow, bail, Context, Error, Result};
 use clap::{Args, Parser, Subcommand};
-use std::path::Path;
+use colored::Colorize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -58,6 +67,11 @@ enum Commands {
     ///
     /// Removes a project from the `nixspace.toml`.
     Unregister(Unregister),
+    /// register many projects at once
+    ///
+    /// Registers every direct input of a `flake.lock`, or every flake with a
+    /// git remote found under a directory.
+    Import(Import),
 
     // LOCAL PROJECT COMMANDS
     /// link a project to the workspace locally
@@ -70,6 +84,25 @@ enum Commands {
     /// Disassociates the project from the workspace, meaning that future builds
     /// use the locked version of the package rather than the local.
     Unedit(Unedit),
+    /// link a different worktree of an editable project
+    ///
+    /// Flips the workspace to a worktree created with `ns edit --branch
+    /// <branch> --worktree`, or back to the project's main checkout.
+    Switch(Switch),
+    /// work on a branch across several projects
+    ///
+    /// Creates a branch of the same name in each editable project, and pins
+    /// the branches in a `changeset-<name>` environment so that the whole
+    /// change can be built and reviewed as one unit.
+    #[command(subcommand)]
+    Changeset(ChangesetSubcommand),
+    /// show the local state of projects in the workspace
+    Status(Status),
+    /// run a command in every local project checkout
+    ///
+    /// Runs the command after `--` from the directory of each selected project
+    /// that has been cloned locally.
+    Foreach(Foreach),
 
     // GIT MANAGEMENT
     /// pull the workspace config + lockfile from the upstream remote
@@ -86,6 +119,12 @@ enum Commands {
     ///
     /// Updates all projects in the workspace lockfile.
     Update(Update),
+    /// inspect the workspace lockfiles
+    #[command(subcommand)]
+    Lock(LockSubcommand),
+    /// check projects and their inputs against the workspace policy
+    #[command(subcommand)]
+    Policy(PolicySubcommand),
 
     // NIX ALIASES
     /// alias for "nix build" executed from the workspace context
@@ -93,8 +132,11 @@ enum Commands {
     /// When run within a project directory, will build the associated project
     /// in the context of the workspace, allowing for seamless testing of changes.
     ///
+    /// With `--all-consumers <SELECTOR>`, builds the default package of the
+    /// selected projects and every project that depends on them instead.
+    ///
     /// See `nix build --help` for any details on the nix command.
-    Build(NixArgs),
+    Build(Build),
     /// alias for "nix run" executed from the workspace context
     ///
     /// When run within a project directory, will build the associated project
@@ -138,7 +180,6 @@ impl Command for Init {
         let cmd = ["flake", "init", "-t", target];
         Nix::exec(&cmd, &dir)?;
         Git::init(&dir)?;
-        Git::add("flake.nix")?;
         let ws = Workspace::at(&dir)?;
         ws.commit("initial commit")?;
         println!("workspace initialized at {} with {target}", self.name);
@@ -160,11 +201,10 @@ struct Clone {
 impl Command for Clone {
     fn run(&self) -> Result<()> {
         let flake_ref = flake::parse(&self.flake_ref)?;
-        let input_spec = InputSpec::from_flake_ref(flake_ref);
         let dest: String = match &self.directory {
             Some(dirname) => dirname.to_string(),
-            _ =>
-                input_spec.owner.expect("could not infer project name from input spec; specify --directory for the destination dir."),
+            _ => flake_ref.infer_name()
+                .context("could not infer workspace name from flake reference; specify the destination directory")?,
         };
         Nix::clone(&self.flake_ref, &dest, ".")?;
         Ok(())
@@ -173,12 +213,18 @@ impl Command for Clone {
 
 #[derive(Args, Debug)]
 struct Show {
+    /// if present, only show projects that are members of this environment
+    #[arg(short, long)]
+    env: Option<String>,
 }
 
 impl Command for Show {
     fn run(&self) -> Result<()> {
         let ws = Workspace::discover()?;
-        ws.print_tree();
+        if let Some(env) = &self.env {
+            ws.config.env(env)?;
+        }
+        ws.print_tree(&self.env);
         Ok(())
     }
 }
@@ -232,10 +278,59 @@ impl Command for ConfigSubcommand {
 
 #[derive(Debug, Subcommand)]
 enum EnvSubcommand {
+    /// list all environments
+    #[command(alias = "ls")]
+    List(EnvList),
+    /// create a new environment
+    Add(EnvAdd),
+    /// remove an environment and its lockfile
+    #[command(alias = "remove")]
+    Rm(EnvRm),
+    /// rename an environment
+    #[command(alias = "mv")]
+    Rename(EnvRename),
     /// get a configuration value
     Get(EnvGet),
     /// set a configuration value
     Set(EnvSet),
+    /// create a throwaway environment with some projects on branches
+    ///
+    /// Copies the lockfile of a base environment and pins the given projects
+    /// to the head of a branch, for example to review changes across
+    /// several repositories. Previews are flake outputs like any other
+    /// environment.
+    Preview(EnvPreview),
+}
+
+#[derive(Args, Debug)]
+struct EnvList {
+}
+
+#[derive(Args, Debug)]
+struct EnvAdd {
+    /// environment name
+    name: String,
+    /// update strategy for the environment; for example `latest` or
+    /// `{"branch": "main"}`. defaults to the strategy of `--from`, or `latest`
+    #[arg(short, long)]
+    strategy: Option<UpdateStrategy>,
+    /// if present, copies the lockfile and project settings of this environment
+    #[arg(long)]
+    from: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct EnvRm {
+    /// environment name
+    name: String,
+}
+
+#[derive(Args, Debug)]
+struct EnvRename {
+    /// current environment name
+    name: String,
+    /// new environment name
+    new_name: String,
 }
 
 #[derive(Args, Debug)]
@@ -256,15 +351,113 @@ struct EnvSet {
     value: String,
 }
 
+#[derive(Args, Debug)]
+struct EnvPreview {
+    /// name of the preview environment. an existing preview of the same
+    /// name is replaced
+    #[arg(required_unless_present = "gc", conflicts_with = "gc")]
+    name: Option<String>,
+    /// pin a project to the head of a branch (or to a revision); may be
+    /// repeated
+    #[arg(short, long = "project", value_name = "PROJECT=BRANCH")]
+    projects: Vec<String>,
+    /// pin every project of a changeset to the changeset's branch
+    #[arg(long)]
+    changeset: Option<String>,
+    /// environment to copy. default is the default environment
+    #[arg(long)]
+    base: Option<String>,
+    /// instead of creating a preview, remove previews older than this many
+    /// days
+    #[arg(long, value_name = "DAYS", num_args = 0..=1, default_missing_value = "7")]
+    gc: Option<u64>,
+}
+
+impl EnvPreview {
+    fn run(&self) -> Result<()> {
+        let mut ws = Workspace::discover()?;
+        if let Some(days) = self.gc {
+            let removed = ws.gc_previews(days)?;
+            ws.save()?;
+            for env in &removed {
+                ws.stage(&ws.lock_path(env));
+                println!("removed preview {env}");
+            }
+            return Ok(());
+        }
+        let name = self.name.as_ref().unwrap();
+        let mut branches = BTreeMap::new();
+        if let Some(changeset) = &self.changeset {
+            let projects = ws.local.changesets.get(changeset)
+                .with_context(|| anyhow!("no changeset named '{changeset}'"))?
+                .projects.keys();
+            branches.extend(projects.map(|p| (p.to_string(), changeset.to_string())));
+        }
+        for project in &self.projects {
+            let (project, branch) = project.split_once('=')
+                .with_context(|| anyhow!("expected PROJECT=BRANCH, got '{project}'"))?;
+            branches.insert(project.to_string(), branch.to_string());
+        }
+        if branches.is_empty() {
+            bail!("nothing to preview; pass --project or --changeset");
+        }
+        let pins = ws.preview_env(name, &self.base, &branches)?;
+        ws.save()?;
+        ws.stage(&ws.lock_path(name));
+        for (project, rev) in &pins {
+            println!("{:030} {} ({})", project.bold(), branches[project], &rev[..rev.len().min(7)]);
+        }
+        println!("created preview environment {name}");
+        Ok(())
+    }
+}
+
 impl Command for EnvSubcommand {
     fn run(&self) -> Result<()> {
         match &self {
+            EnvSubcommand::List(_) => {
+                let ws = Workspace::discover()?;
+                for env in &ws.config.environments {
+                    let marker = if env.name == ws.config.default_env { "*" } else { " " };
+                    let preview = match &env.preview {
+                        Some(p) => format!(" (preview of {})", p.base),
+                        None => "".to_string(),
+                    };
+                    println!("{marker} {} {}{preview}", env.name, serde_json::to_string(&env.strategy)?);
+                }
+            },
+            EnvSubcommand::Add(add) => {
+                let mut ws = Workspace::discover()?;
+                ws.add_env(&add.name, add.strategy.clone(), &add.from)?;
+                ws.save()?;
+                ws.stage(&ws.lock_path(&add.name));
+                println!("created environment {}", add.name);
+            },
+            EnvSubcommand::Rm(rm) => {
+                let mut ws = Workspace::discover()?;
+                ws.rm_env(&rm.name)?;
+                ws.save()?;
+                ws.stage(&ws.lock_path(&rm.name));
+                println!("removed environment {}", rm.name);
+            },
+            EnvSubcommand::Rename(rename) => {
+                let mut ws = Workspace::discover()?;
+                ws.rename_env(&rename.name, &rename.new_name)?;
+                ws.save()?;
+                ws.stage(&ws.lock_path(&rename.name));
+                ws.stage(&ws.lock_path(&rename.new_name));
+                println!("renamed environment {} to {}", rename.name, rename.new_name);
+            },
             EnvSubcommand::Get(get) => {
                 let ws = Workspace::discover()?;
                 let env = ws.config.env(&get.env)?;
                 match get.name.as_str() {
                     // todo: serialize
                     "strategy" => println!("{}", serde_json::to_string(&env.strategy)?),
+                    "verify" => match &env.verify {
+                        Some(verify) => println!("{}", serde_json::to_value(verify)?.as_str().unwrap_or_default()),
+                        None => println!("none"),
+                    },
                     _ => bail!("error: unrecognized environment key '{}'", get.name),
                 }
             },
@@ -275,10 +468,18 @@ impl Command for EnvSubcommand {
                     "strategy" => {
                         env.strategy = serde_json::from_str(&set.value)?;
                     },
+                    "verify" => {
+                        env.verify = match set.value.as_str() {
+                            "none" => None,
+                            value => Some(serde_json::from_value(serde_json::Value::String(value.to_string()))
+                                .with_context(|| anyhow!("expected signed-tag, signed-commit or none, got '{value}'"))?),
+                        };
+                    },
                     _ => bail!("error: unrecognized environment key '{}'", set.name),
                 }
                 ws.save()?;
             },
+            EnvSubcommand::Preview(preview) => preview.run()?,
         };
         Ok(())
     }
@@ -287,7 +488,12 @@ impl Command for EnvSubcommand {
 #[derive(Args, Debug)]
 struct Register {
     /// flake reference to the project; for example github:chadac/nixspace
-    url: String,
+    #[arg(required_unless_present = "from_path")]
+    url: Option<String>,
+    /// register an existing checkout inside the workspace as an editable
+    /// project. the flake reference is derived from its git remote
+    #[arg(long, conflicts_with_all = ["url", "path", "edit"])]
+    from_path: Option<PathBuf>,
     /// name of the directory that the project will be cloned into when added.
     /// default is the name of the project at the root of the workspace.
     #[arg(short, long)]
@@ -299,20 +505,49 @@ struct Register {
     /// if present, clones the project locally
     #[arg(long)]
     edit: bool,
+    /// tags for addressing the project in selectors; may be repeated
+    #[arg(short, long = "tag")]
+    tags: Vec<String>,
+    /// environments to include the project in; may be repeated.
+    /// default is every environment
+    #[arg(short, long = "env")]
+    envs: Vec<String>,
+    /// environments to exclude the project from; may be repeated
+    #[arg(long = "exclude-env")]
+    exclude_envs: Vec<String>,
 }
 
 impl Command for Register {
     fn run(&self) -> Result<()> {
         let mut ws = Workspace::discover()?;
-        let flake_ref = flake::parse(&self.url)?;
-        let name = match &self.name {
-            Some(n) => n.to_string(),
-            None => flake_ref.infer_name().context("could not infer project name!")?
+        for env in self.envs.iter().chain(self.exclude_envs.iter()) {
+            ws.config.env(env)?;
+        }
+        let (name, url) = match (&self.url, &self.from_path) {
+            (_, Some(dir)) => {
+                let project = ws.register_from_path(dir, &self.name)?;
+                (project.config.name.to_string(), project.config.url.to_string())
+            },
+            (Some(url), None) => {
+                let flake_ref = flake::parse(url)?;
+                let name = match &self.name {
+                    Some(n) => n.to_string(),
+                    None => flake_ref.infer_name().context("could not infer project name!")?
+                };
+                ws.register(&name, flake_ref, &self.path)?;
+                (name, url.to_string())
+            },
+            (None, None) => unreachable!("clap requires either a url or --from-path"),
         };
-        let project = ws.register(&name, flake_ref, &self.path)?;
-
-        if self.edit {
-            ws.edit(&name)?;
+        let project = ws.config.project_mut(&name)?;
+        if !self.envs.is_empty() {
+            project.environments = Some(self.envs.clone());
+        }
+        if !self.exclude_envs.is_empty() {
+            project.exclude_environments = Some(self.exclude_envs.clone());
+        }
+        if !self.tags.is_empty() {
+            project.tags = Some(self.tags.clone());
         }
 
         // update the lockfile
@@ -320,9 +555,13 @@ impl Command for Register {
             ws.update_all_projects(&Some(env))?;
         }
 
+        if self.edit {
+            ws.edit(&name, &None, &None, false)?;
+        }
+
         ws.save()?;
 
-        println!("registered project {name} with url {}", self.url);
+        println!("registered project {name} with url {url}");
         Ok(())
     }
 }
@@ -334,12 +573,16 @@ struct Unregister {
     #[arg(long)]
     /// if present, delete the directory from the workspace
     delete: bool,
+    /// with `--delete`, delete the checkout even if it has uncommitted,
+    /// unpushed or stashed work
+    #[arg(long, requires = "delete")]
+    force: bool,
 }
 
 impl Command for Unregister {
     fn run(&self) -> Result<()> {
         let mut ws = Workspace::discover()?;
-        ws.deregister(&self.name, self.delete)?;
+        ws.deregister(&self.name, self.delete, self.force)?;
         for env in ws.config.environments() {
             ws.update_all_projects(&Some(env))?;
         }
@@ -349,35 +592,246 @@ impl Command for Unregister {
     }
 }
 
+#[derive(Args, Debug)]
+struct Import {
+    /// register every direct flake input of this lockfile, locked to the
+    /// same revision
+    #[arg(long, required_unless_present = "scan", conflicts_with = "scan")]
+    from_flake_lock: Option<PathBuf>,
+    /// register every flake with a git remote under this directory
+    #[arg(long)]
+    scan: Option<PathBuf>,
+    /// how to handle projects whose name is already registered
+    #[arg(long, value_enum, default_value_t = OnConflict::Fail)]
+    on_conflict: OnConflict,
+    /// if present, only print the projects that would be registered
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl Command for Import {
+    fn run(&self) -> Result<()> {
+        let mut ws = Workspace::discover()?;
+        let imports = match (&self.from_flake_lock, &self.scan) {
+            (Some(lock), _) => import::from_flake_lock(lock)?,
+            (None, Some(dir)) => import::scan(&ws.root, dir)?,
+            (None, None) => unreachable!("clap requires either --from-flake-lock or --scan"),
+        };
+        let registered = ws.import(imports, self.on_conflict)?;
+        for project in &registered {
+            println!(
+                "{} {:030} {} {}",
+                if self.dry_run { "would register" } else { "registered" },
+                project.name.bold(),
+                project.flake_ref.flake_url(),
+                project.rev.as_deref().unwrap_or(""),
+            );
+        }
+        if self.dry_run || registered.is_empty() {
+            return Ok(());
+        }
+
+        let names: Vec<String> = registered.iter().map(|p| p.name.to_string()).collect();
+        let pins: BTreeMap<String, String> = registered.iter()
+            .filter_map(|p| p.rev.as_ref().map(|r| (p.name.to_string(), r.to_string())))
+            .collect();
+        for env in ws.config.environments() {
+            ws.lock_projects(&Some(env), &Selector::names(&names), &pins)?;
+        }
+        ws.save()?;
+        Ok(())
+    }
+}
+
 #[derive(Args, Debug)]
 struct Edit {
-    /// name of the project
-    name: String,
+    /// projects to edit; either names, name globs or `@tag`s. prefix with
+    /// `!` to exclude matches
+    #[arg(required = true)]
+    selectors: Vec<String>,
+    /// environment whose locked revision is checked out. defaults to the
+    /// default environment
+    #[arg(short, long)]
+    env: Option<String>,
+    /// branch to check out instead of a new `ns/<env>/<rev>` branch; created
+    /// at the locked revision if it doesn't exist, or tracked if it exists on
+    /// the remote
+    #[arg(short, long)]
+    branch: Option<String>,
+    /// check the branch out in a new git worktree next to the project's
+    /// checkout, rather than switching the checkout to it
+    #[arg(long, requires = "branch")]
+    worktree: bool,
 }
 
 impl Command for Edit {
     fn run(&self) -> Result<()> {
         let mut ws = Workspace::discover()?;
-        ws.edit(&self.name)?;
+        for name in ws.select_names(&self.selectors)? {
+            if ws.local.is_editable(&name) && !self.worktree {
+                println!("{name} is already editable");
+                continue;
+            }
+            ws.edit(&name, &self.env, &self.branch, self.worktree)?;
+            ws.save()?;
+            println!("marked {name} as editable");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Switch {
+    /// project to switch
+    project: String,
+    /// branch of the worktree to link to the workspace
+    branch: String,
+}
+
+impl Command for Switch {
+    fn run(&self) -> Result<()> {
+        let mut ws = Workspace::discover()?;
+        ws.switch(&self.project, &self.branch)?;
         ws.save()?;
+        println!("switched {} to {}", self.project, self.branch);
         Ok(())
     }
 }
 
 #[derive(Args, Debug)]
 struct Unedit {
-    /// name of the project
-    name: String,
+    /// projects to unlink; either names, name globs or `@tag`s. prefix with
+    /// `!` to exclude matches
+    #[arg(required = true)]
+    selectors: Vec<String>,
     /// if present, deletes the project locally
     #[arg(long)]
-    rm: bool
+    rm: bool,
+    /// with `--rm`, delete the checkout even if it has uncommitted,
+    /// unpushed or stashed work
+    #[arg(long, requires = "rm")]
+    force: bool,
 }
 
 impl Command for Unedit {
     fn run(&self) -> Result<()> {
         let mut ws = Workspace::discover()?;
-        ws.unedit(&self.name, self.rm)?;
-        ws.save()?;
+        for name in ws.select_names(&self.selectors)? {
+            ws.unedit(&name, self.rm, self.force)?;
+            ws.save()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum ChangesetSubcommand {
+    /// check out a branch in every selected editable project
+    ///
+    /// Running it again with other projects adds them to the changeset.
+    Start(ChangesetStart),
+    /// show the commits and local changes of each project in a changeset
+    Status(ChangesetName),
+    /// push every branch of a changeset and pin them in its environment
+    Push(ChangesetName),
+}
+
+#[derive(Args, Debug)]
+struct ChangesetStart {
+    /// name of the changeset, used as the branch name in every project
+    name: String,
+    /// projects to include; either names, name globs or `@tag`s. default is
+    /// all editable projects
+    selectors: Vec<String>,
+    /// environment that the changeset's environment is based on. default is
+    /// the default environment
+    #[arg(long)]
+    env: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct ChangesetName {
+    /// name of the changeset
+    name: String,
+}
+
+impl Command for ChangesetSubcommand {
+    fn run(&self) -> Result<()> {
+        match &self {
+            ChangesetSubcommand::Start(start) => {
+                let mut ws = Workspace::discover()?;
+                let added = ws.changeset_start(&start.name, &Selector::parse(&start.selectors)?, &start.env)?;
+                ws.save()?;
+                match added.is_empty() {
+                    true => println!("'{}' already includes the selected projects", start.name),
+                    false => println!("added {} to changeset '{}'", added.join(", "), start.name),
+                }
+            },
+            ChangesetSubcommand::Status(status) => {
+                let ws = Workspace::discover()?;
+                ws.print_changeset(&status.name)?;
+            },
+            ChangesetSubcommand::Push(push) => {
+                let mut ws = Workspace::discover()?;
+                let heads = ws.changeset_push(&push.name)?;
+                let env = ws.lock_changeset(&push.name, &heads)?;
+                ws.save()?;
+                ws.stage(&ws.lock_path(&env));
+                println!("pinned {} projects in environment '{env}'", heads.len());
+            },
+        };
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct Status {
+    /// projects to show; either names, name globs or `@tag`s. default is all
+    /// projects
+    selectors: Vec<String>,
+}
+
+impl Command for Status {
+    fn run(&self) -> Result<()> {
+        let ws = Workspace::discover()?;
+        ws.print_status(&Selector::parse(&self.selectors)?)
+    }
+}
+
+#[derive(Args, Debug)]
+struct Foreach {
+    /// projects to run the command in; either names, name globs or `@tag`s.
+    /// default is all projects
+    selectors: Vec<String>,
+    /// command to run
+    #[arg(last = true, required = true)]
+    command: Vec<String>,
+}
+
+impl Command for Foreach {
+    fn run(&self) -> Result<()> {
+        let ws = Workspace::discover()?;
+        let mut failed = Vec::new();
+        for project in ws.select(&Selector::parse(&self.selectors)?) {
+            let path = match project.flake_path().map(|p| ws.resolve(&p)).transpose()? {
+                Some(p) if p.exists() => p,
+                _ => {
+                    log::debug!("skipping {}; not cloned locally", project.config.name);
+                    continue;
+                },
+            };
+            println!("{}", format!("==> {}", project.config.name).bold());
+            let status = std::process::Command::new(&self.command[0])
+                .args(&self.command[1..])
+                .current_dir(&path)
+                .status()?;
+            if !status.success() {
+                failed.push(project.config.name.to_string());
+            }
+        }
+        if !failed.is_empty() {
+            bail!("command failed in: {}", failed.join(", "));
+        }
         Ok(())
     }
 }
@@ -386,13 +840,52 @@ impl Command for Unedit {
 struct Sync {
     /// will sync all local repositories with upstream
     #[arg(long)]
-    local: bool
+    local: bool,
+    /// how to update checkouts with local commits. default is `sync` in
+    /// nixspace.toml, or fast-forward
+    #[arg(long, requires = "local")]
+    mode: Option<SyncMode>,
+    /// number of checkouts to update at once
+    #[arg(short, long, default_value_t = 4, requires = "local")]
+    jobs: usize,
+    /// environment whose locked revisions checkouts are compared to.
+    /// default is the default environment
+    #[arg(long, requires = "local")]
+    env: Option<String>,
 }
 
 impl Command for Sync {
     fn run(&self) -> Result<()> {
         let mut ws = Workspace::discover()?;
-        ws.sync()?;
+        if !self.local {
+            return ws.sync();
+        }
+        let mode = self.mode.unwrap_or(ws.config.sync);
+        let mut conflicts = Vec::new();
+        for (name, outcome) in ws.sync_local(mode, self.jobs)? {
+            let state = match &outcome {
+                SyncOutcome::UpToDate => "up to date".dimmed(),
+                SyncOutcome::Updated { from, to } => format!("updated {}..{}", &from[..7], &to[..7]).green(),
+                SyncOutcome::Skipped(reason) => format!("skipped: {reason}").yellow(),
+                SyncOutcome::Conflict(reason) => format!("conflict: {reason}").red(),
+            };
+            println!("{:030} {}", name.bold(), state);
+            if let SyncOutcome::Conflict(_) = outcome {
+                conflicts.push(name);
+            }
+        }
+
+        let env = self.env.clone().unwrap_or(ws.config.default_env.to_string());
+        let ahead = ws.ahead_of_lock(&env)?;
+        if !ahead.is_empty() {
+            println!("\nahead of their locked revisions in '{env}':");
+            for (name, commits) in ahead {
+                println!("{:030} {}", name.bold(), util::commits(commits));
+            }
+        }
+        if !conflicts.is_empty() {
+            bail!("could not sync: {}", conflicts.join(", "));
+        }
         Ok(())
     }
 }
@@ -402,6 +895,8 @@ struct Publish {
     /// commit message to include in publish
     #[arg(short, long)]
     message: String,
+    /// publish even if the remote branch has commits that are not in the
+    /// workspace, overwriting them. uses `git push --force-with-lease`
     #[arg(short, long)]
     force: bool,
 }
@@ -409,8 +904,9 @@ struct Publish {
 impl Command for Publish {
     fn run(&self) -> Result<()> {
         let ws = Workspace::discover()?;
+        let target = ws.check_publish(self.force)?;
         ws.commit(&self.message)?;
-        ws.publish(self.force)?;
+        ws.publish(&target, self.force)?;
         Ok(())
     }
 }
@@ -422,26 +918,134 @@ struct Update {
     /// if present, publishes the new lockfile to the Git repository
     #[arg(long)]
     publish: bool,
+    /// commit the new lockfile on a new branch, push it and open a review
+    /// request with the forge in nixspace.toml. default branch name is
+    /// `ns/update-<env>-<date>`
+    #[arg(long, value_name = "BRANCH", num_args = 0..=1, conflicts_with = "publish")]
+    branch: Option<Option<String>>,
+    /// only update the selected projects; may be repeated. other projects
+    /// stay at their locked revision
+    #[arg(short, long = "project", value_name = "SELECTOR")]
+    projects: Vec<String>,
+    /// template for the commit message when publishing. `{summary}`,
+    /// `{changes}`, `{env}`, `{count}` and `{projects}` are replaced with
+    /// details of the update. default is "{summary}\n\n{changes}"
+    #[arg(long)]
+    message_template: Option<String>,
 }
 
 impl Command for Update {
     fn run(&self) -> Result<()> {
         let mut ws = Workspace::discover()?;
-        ws.update_all_projects(&self.env)?;
+        let env = self.env.clone().unwrap_or(ws.config.default_env.to_string());
+        let old = ws.lock.get(&env).cloned()
+            .with_context(|| anyhow!("error: workspace config missing env '{env}'"))?;
+        ws.update_projects(&Some(env.clone()), &Selector::parse(&self.projects)?)?;
         ws.save()?;
-        if self.publish {
-            if ws.tracks_latest()? {
-                ws.commit("chore: update workspace")?;
-                ws.publish(false)?;
+        if self.publish || self.branch.is_some() {
+            let changelog = ws.changelog(&env, &old)?;
+            if changelog.changes.is_empty() {
+                println!("'{env}' is already up to date; nothing to publish");
+                return Ok(());
             }
-            else {
-                bail!("cannot commit; upstream is ahead of local git repository.");
+            let message = changelog.render(self.message_template.as_deref().unwrap_or(changelog::DEFAULT_TEMPLATE));
+            match &self.branch {
+                Some(branch) => {
+                    let branch = branch.clone().unwrap_or(format!("ns/update-{env}-{}", util::today()));
+                    let forge = forge::from_config(&ws.config.forge);
+                    println!("{}", ws.propose(&branch, &message, forge.as_ref())?);
+                },
+                None => {
+                    let target = ws.check_publish(false)?;
+                    ws.commit(&message)?;
+                    ws.publish(&target, false)?;
+                },
             }
         }
         Ok(())
     }
 }
 
+#[derive(Debug, Subcommand)]
+enum LockSubcommand {
+    /// check that a lockfile could have been produced by `ns update`
+    ///
+    /// Fails if a locked revision is missing from its remote or is not on a
+    /// ref followed by the project's strategy, if a `narHash` doesn't match
+    /// the fetched source, or if the nodes differ from merging the lockfiles
+    /// of the locked projects. Meant to run in CI on workspace changes.
+    Verify(LockVerify),
+}
+
+#[derive(Args, Debug)]
+struct LockVerify {
+    /// environment to verify. default is the default environment
+    env: Option<String>,
+}
+
+impl Command for LockSubcommand {
+    fn run(&self) -> Result<()> {
+        match &self {
+            LockSubcommand::Verify(verify) => {
+                let ws = Workspace::discover()?;
+                let env = verify.env.clone().unwrap_or(ws.config.default_env.to_string());
+                let issues = ws.verify_lock(&env)?;
+                for issue in &issues {
+                    println!("{} {}", format!("{}:", issue.node).red(), issue.problem);
+                }
+                if !issues.is_empty() {
+                    bail!("the lockfile of '{env}' failed verification with {} problem(s)", issues.len());
+                }
+                println!("the lockfile of '{env}' is verified");
+            },
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum PolicySubcommand {
+    /// report sources that the `[policy]` in nixspace.toml does not allow
+    ///
+    /// Checks every registered project and every input in the lockfiles,
+    /// including the inputs of each project's own `flake.lock`.
+    Check(PolicyCheck),
+}
+
+#[derive(Args, Debug)]
+struct PolicyCheck {
+    /// only check the lockfile of this environment. default is every
+    /// environment
+    #[arg(long)]
+    env: Option<String>,
+}
+
+impl Command for PolicySubcommand {
+    fn run(&self) -> Result<()> {
+        match &self {
+            PolicySubcommand::Check(check) => {
+                let ws = Workspace::discover()?;
+                let envs = match &check.env {
+                    Some(env) => vec![env.to_string()],
+                    None => ws.config.environments(),
+                };
+                let violations = ws.check_policy(&envs)?;
+                for (env, violation) in &violations {
+                    match env {
+                        Some(env) => println!("{} {violation}", format!("[{env}]").dimmed()),
+                        None => println!("{violation}"),
+                    }
+                }
+                if !violations.is_empty() {
+                    bail!("{} source(s) are not allowed by the workspace policy", violations.len());
+                }
+                println!("all sources are allowed by the workspace policy");
+            },
+        }
+        Ok(())
+    }
+}
+
 #[derive(Args, Debug)]
 struct NixArgs {
     #[arg(trailing_var_arg = true, allow_hyphen_values = true, hide = true)]
@@ -506,6 +1110,45 @@ impl NixArgs {
     }
 }
 
+#[derive(Args, Debug)]
+struct Build {
+    /// build the default package of the selected projects and all of their
+    /// consumers; may be repeated
+    #[arg(long, value_name = "SELECTOR")]
+    all_consumers: Vec<String>,
+    /// environment used to determine consumers
+    #[arg(long)]
+    env: Option<String>,
+    #[command(flatten)]
+    nix: NixArgs,
+}
+
+impl Build {
+    fn run(&self) -> Result<()> {
+        if self.all_consumers.is_empty() {
+            return self.nix.run("build");
+        }
+        let ws = Workspace::discover()?;
+        let env = self.env.clone().unwrap_or(ws.config.default_env.to_string());
+        let selected = ws.select_names(&self.all_consumers)?;
+        let mut projects = ws.consumers(&env, &selected)?;
+        projects.extend(selected);
+        projects.sort();
+        projects.dedup();
+
+        let root = std::fs::canonicalize(&ws.root)?;
+        let mut args = vec!["build".to_string(), "--impure".to_string()];
+        args.extend(projects.iter().map(|p| format!("path:{}#{p}/default", root.display())));
+        args.extend(self.nix.args.iter().cloned());
+        println!("building {}", projects.join(", "));
+        Nix::interactive(
+            &args.iter().map(|s| s.as_str()).collect::<Vec<_>>()[..],
+            &std::env::current_dir()?,
+        )?;
+        Ok(())
+    }
+}
+
 fn exec(command: &Commands) -> Result<()> {
     match command {
         Commands::Init(cmd) => cmd.run(),
@@ -517,15 +1160,22 @@ fn exec(command: &Commands) -> Result<()> {
 
         Commands::Register(cmd) => cmd.run(),
         Commands::Unregister(cmd) => cmd.run(),
+        Commands::Import(cmd) => cmd.run(),
 
         Commands::Edit(cmd) => cmd.run(),
         Commands::Unedit(cmd) => cmd.run(),
+        Commands::Switch(cmd) => cmd.run(),
+        Commands::Changeset(cmd) => cmd.run(),
+        Commands::Status(cmd) => cmd.run(),
+        Commands::Foreach(cmd) => cmd.run(),
 
         Commands::Sync(cmd) => cmd.run(),
         Commands::Publish(cmd) => cmd.run(),
         Commands::Update(cmd) => cmd.run(),
+        Commands::Lock(cmd) => cmd.run(),
+        Commands::Policy(cmd) => cmd.run(),
 
-        Commands::Build(nix) => nix.run("build"),
+        Commands::Build(build) => build.run(),
         Commands::Run(nix) => nix.run("run"),
     }?;
     Ok(())
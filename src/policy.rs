@@ -0,0 +1,236 @@
+use glob_match::glob_match;
+use serde::{Serialize, Deserialize};
+
+use super::flake::{is_rev, is_template, normalize_remote, FlakeRef, CASE_INSENSITIVE_HOSTS};
+use super::lockfile::{FlakeType, InputSpec, LockFile};
+
+/// Which sources projects and their inputs may be fetched from, set by the
+/// `[policy]` section of nixspace.toml.
+///
+/// Patterns take one of three forms:
+///
+/// * `github:` or `git+*:` match the scheme of a flake reference, so that
+///   `path:` bans local paths;
+/// * `git.example.com` or `*.example.com` match the host; and
+/// * `github.com/my-org/*` matches the repository, as `<host>/<path>`.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, Debug)]
+pub struct PolicyConfig {
+    /// if not empty, sources must match one of these patterns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+    /// sources matching any of these patterns are rejected
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+    /// reject tarballs whose URL may serve different contents over time
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deny_unpinned_tarballs: bool,
+}
+
+/// A source that is not allowed by the policy.
+#[derive(PartialEq, Debug)]
+pub struct Violation {
+    /// path of the input from the root of the lockfile, such as
+    /// `app/utils/nixpkgs`, or the name of a registered project
+    pub input: String,
+    pub url: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.input, self.url, self.reason)
+    }
+}
+
+/// The scheme of a reference as written in its canonical URL, such as
+/// `github` or `git+https`.
+fn scheme(flake_ref: &dyn FlakeRef) -> String {
+    let url = flake_ref.canonical_url();
+    url.split(':').next().unwrap_or_default().to_string()
+}
+
+/// The `<host>/<path>` a reference is fetched from, if it has a host. This
+/// is taken from the URL that is actually fetched, so that parameters like
+/// `?host=` are honored.
+fn location(flake_ref: &dyn FlakeRef) -> Option<String> {
+    match flake_ref.flake_type() {
+        FlakeType::Path | FlakeType::Indirect => None,
+        _ => flake_ref.repository()
+            .or_else(|| flake_ref.hg_remote_url())
+            .or_else(|| flake_ref.arg("url"))
+            .map(|url| normalize_remote(url.split('?').next().unwrap())),
+    }
+}
+
+fn matches(pattern: &str, scheme: &str, location: Option<&str>) -> bool {
+    let (pattern, location) = match (pattern.strip_suffix(':'), location) {
+        (Some(p), _) => return glob_match(p, scheme),
+        (None, Some(l)) => (pattern, l),
+        (None, None) => return false,
+    };
+    let host = location.split('/').next().unwrap();
+    // canonical locations on these hosts are lowercase
+    let pattern = match CASE_INSENSITIVE_HOSTS.contains(&host) {
+        true => pattern.to_lowercase(),
+        false => pattern.to_string(),
+    };
+    match pattern.contains('/') {
+        true => glob_match(&pattern, location),
+        false => glob_match(&pattern, host),
+    }
+}
+
+/// True if a tarball URL always serves the same contents: it records a
+/// `narHash`, contains a commit hash, or is a versioned template.
+fn pinned(flake_ref: &dyn FlakeRef) -> bool {
+    let url = flake_ref.arg("url").unwrap_or_default();
+    flake_ref.arg("narHash").is_some()
+        || is_template(&url)
+        || url.split(['/', '?', '&', '=']).any(|part| is_rev(part.split('.').next().unwrap()))
+}
+
+impl PolicyConfig {
+    pub fn is_default(&self) -> bool {
+        *self == PolicyConfig::default()
+    }
+
+    /// Returns why a source is not allowed, or `None` if it is.
+    pub fn check(&self, flake_ref: &dyn FlakeRef) -> Option<String> {
+        self.check_source(flake_ref).or_else(|| self.check_pinned(flake_ref))
+    }
+
+    /// Checks where a source is fetched from against the allow and deny
+    /// patterns.
+    fn check_source(&self, flake_ref: &dyn FlakeRef) -> Option<String> {
+        let scheme = scheme(flake_ref);
+        let location = location(flake_ref);
+        let matching = |patterns: &[String]| patterns.iter()
+            .find(|p| matches(p, &scheme, location.as_deref()))
+            .cloned();
+        if let Some(pattern) = matching(&self.deny) {
+            return Some(format!("denied by '{pattern}'"));
+        }
+        if !self.allow.is_empty() && matching(&self.allow).is_none() {
+            return Some("not matched by any allowed pattern".to_string());
+        }
+        None
+    }
+
+    fn check_pinned(&self, flake_ref: &dyn FlakeRef) -> Option<String> {
+        let tarball = matches!(flake_ref.flake_type(), FlakeType::Tarball | FlakeType::File);
+        match self.deny_unpinned_tarballs && tarball && !pinned(flake_ref) {
+            true => Some("tarball is not pinned to a hash, revision or version".to_string()),
+            false => None,
+        }
+    }
+
+    /// Checks every input in a lockfile, including the inputs of inputs.
+    /// Where an input is fetched from is checked against its locked
+    /// reference, and whether a tarball is pinned against how it was
+    /// written.
+    pub fn check_lock(&self, lock: &LockFile) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for (path, node) in lock.input_paths() {
+            let reference = |spec: Option<InputSpec>| spec.and_then(|s| s.to_flake_ref().ok());
+            let locked = reference(lock.get_input_spec(&node));
+            let original = reference(lock.get_original_spec(&node));
+            let found = locked.as_ref().or(original.as_ref())
+                .and_then(|r| Some((r.flake_url(), self.check_source(r.as_ref())?)))
+                .or_else(|| original.as_ref().and_then(|r| Some((r.flake_url(), self.check_pinned(r.as_ref())?))));
+            if let Some((url, reason)) = found {
+                violations.push(Violation { input: path.join("/"), url, reason });
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flake::parse;
+
+    fn policy() -> PolicyConfig {
+        toml::from_str(r#"
+            allow = ["github:", "git.example.com", "gitlab.com/my-org/*"]
+            deny = ["path:", "github.com/evil/*"]
+            deny_unpinned_tarballs = true
+        "#).unwrap()
+    }
+
+    #[test]
+    fn test_check() {
+        let check = |url: &str| policy().check(parse(url).unwrap().as_ref());
+        assert_eq!(check("github:chadac/nixspace"), None);
+        assert_eq!(check("github:Evil/thing"), Some("denied by 'github.com/evil/*'".to_string()));
+        assert_eq!(check("git+https://git.example.com/team/repo.git"), None);
+        assert_eq!(check("gitlab:my-org/repo"), None);
+        assert_eq!(check("gitlab:other-org/repo"), Some("not matched by any allowed pattern".to_string()));
+        assert_eq!(check("path:./local"), Some("denied by 'path:'".to_string()));
+        let nixos = PolicyConfig { allow: vec!["github.com/NixOS/*".to_string()], ..Default::default() };
+        assert_eq!(nixos.check(parse("github:NixOS/nixpkgs").unwrap().as_ref()), None);
+
+        let org = PolicyConfig {
+            allow: vec!["github.com/chadac/*".to_string(), "gitlab.com/chadac/*".to_string()],
+            ..Default::default()
+        };
+        let check = |url: &str| org.check(parse(url).unwrap().as_ref());
+        assert_eq!(check("github:chadac/app?host=GitHub.com"), None);
+        assert!(check("github:chadac/app?host=git.evil.com").is_some());
+        assert!(check("gitlab:chadac/app?host=gitlab.evil.com").is_some());
+        let deny = PolicyConfig { deny: vec!["git.evil.com".to_string()], ..Default::default() };
+        assert!(deny.check(parse("github:chadac/app?host=git.evil.com").unwrap().as_ref()).is_some());
+
+        let policy = PolicyConfig { deny_unpinned_tarballs: true, ..Default::default() };
+        let check = |url: &str| policy.check(parse(url).unwrap().as_ref());
+        assert!(check("https://example.com/foo/archive/main.tar.gz").is_some());
+        assert_eq!(check(&format!("https://example.com/foo/archive/{}.tar.gz", "a".repeat(40))), None);
+        assert_eq!(check("https://example.com/foo-{version}.tar.gz"), None);
+        assert_eq!(check("https://example.com/foo.tar.gz?narHash=sha256-AAAA"), None);
+        assert!(PolicyConfig::default().is_default());
+    }
+
+    #[test]
+    fn test_check_lock() {
+        let lock: LockFile = serde_json::from_str(r#"{
+            "nodes": {
+                "root": { "inputs": { "app": "app", "mirror": "mirror" } },
+                "app": {
+                    "inputs": { "utils": "app_utils", "nixpkgs": "app_nixpkgs" },
+                    "locked": { "type": "github", "owner": "chadac", "repo": "app", "rev": "1111111111111111111111111111111111111111" },
+                    "original": { "type": "github", "owner": "chadac", "repo": "app" }
+                },
+                "app_utils": {
+                    "inputs": { "nixpkgs": ["app", "nixpkgs"], "blob": "app_utils_blob" },
+                    "locked": { "type": "github", "owner": "evil", "repo": "utils", "rev": "2222222222222222222222222222222222222222" },
+                    "original": { "type": "indirect", "id": "utils" }
+                },
+                "app_utils_blob": {
+                    "locked": { "type": "tarball", "url": "https://example.com/blob.tar.gz", "narHash": "sha256-AAAA" },
+                    "original": { "type": "tarball", "url": "https://example.com/blob.tar.gz" }
+                },
+                "app_nixpkgs": {
+                    "locked": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "3333333333333333333333333333333333333333" },
+                    "original": { "type": "github", "owner": "NixOS", "repo": "nixpkgs" }
+                },
+                "mirror": {
+                    "locked": { "type": "github", "owner": "chadac", "repo": "app", "host": "git.evil.com", "rev": "4444444444444444444444444444444444444444" },
+                    "original": { "type": "github", "owner": "chadac", "repo": "app", "host": "git.evil.com" }
+                }
+            },
+            "root": "root",
+            "version": 7
+        }"#).unwrap();
+        let inputs = |policy: &PolicyConfig| policy.check_lock(&lock).into_iter()
+            .map(|v| format!("{}: {}", v.input, v.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(inputs(&policy()), vec![
+            "app/utils: denied by 'github.com/evil/*'",
+            "app/utils/blob: not matched by any allowed pattern",
+        ]);
+        let github = PolicyConfig { allow: vec!["github.com/**".to_string(), "example.com".to_string()], ..Default::default() };
+        assert_eq!(inputs(&github), vec!["mirror: not matched by any allowed pattern"]);
+        let policy = PolicyConfig { deny_unpinned_tarballs: true, ..Default::default() };
+        assert_eq!(inputs(&policy), vec!["app/utils/blob: tarball is not pinned to a hash, revision or version"]);
+    }
+}
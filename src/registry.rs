@@ -0,0 +1,183 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Serialize, Deserialize};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use super::flake::FlakeRef;
+use super::lockfile::{FlakeType, InputSpec};
+
+static WORKSPACE_REGISTRY_PATH: &str = ".nixspace/registry.json";
+static SYSTEM_REGISTRY_PATH: &str = "/etc/nix/registry.json";
+
+/// indirect references that resolve to each other more than this many times
+/// are assumed to be cyclic
+const MAX_DEPTH: usize = 16;
+
+/// The on-disk format of a Nix flake registry.
+#[derive(Serialize, Deserialize, Debug)]
+struct RegistryFile {
+    version: u32,
+    flakes: Vec<RegistryEntry>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RegistryEntry {
+    pub from: InputSpec,
+    pub to: InputSpec,
+    /// if true, only references without an extra ref or rev match the entry
+    #[serde(default)]
+    pub exact: bool,
+}
+
+/// Flake registries used to resolve indirect references like
+/// `flake:nixpkgs`, in order of precedence.
+pub struct Registry {
+    entries: Vec<RegistryEntry>,
+}
+
+impl RegistryEntry {
+    fn matches(&self, flake_ref: &dyn FlakeRef) -> bool {
+        let field = |from: &Option<String>, arg: &str| match (from, flake_ref.arg(arg)) {
+            (Some(f), Some(a)) => *f == a,
+            (Some(_), None) => false,
+            (None, Some(_)) => !self.exact,
+            (None, None) => true,
+        };
+        self.from.flake_type == FlakeType::Indirect
+            && self.from.id == flake_ref.arg("id")
+            && field(&self.from.git_ref, "ref")
+            && field(&self.from.rev, "rev")
+    }
+
+    /// Resolves a matching reference, carrying over the ref, rev and
+    /// subdirectory it selects.
+    fn apply(&self, flake_ref: &dyn FlakeRef) -> Result<Rc<dyn FlakeRef>> {
+        let mut to = self.to.clone();
+        if self.from.git_ref.is_none() {
+            if let Some(git_ref) = flake_ref.arg("ref") {
+                to.git_ref = Some(git_ref);
+                to.rev = None;
+            }
+        }
+        if self.from.rev.is_none() {
+            if let Some(rev) = flake_ref.arg("rev") {
+                to.rev = Some(rev);
+            }
+        }
+        if let Some(dir) = flake_ref.arg("dir") {
+            to.dir = Some(dir);
+        }
+        to.to_flake_ref()
+    }
+}
+
+impl Registry {
+    /// Loads the workspace, user and system registries. Registries that are
+    /// missing or can't be read are skipped.
+    pub fn load(root: &Path) -> Self {
+        let mut paths = vec![root.join(WORKSPACE_REGISTRY_PATH)];
+        if let Some(user) = user_registry_path() {
+            paths.push(user);
+        }
+        paths.push(PathBuf::from(SYSTEM_REGISTRY_PATH));
+        Self::from_files(&paths)
+    }
+
+    pub fn from_files(paths: &[PathBuf]) -> Self {
+        let mut entries = Vec::new();
+        for path in paths.iter().filter(|p| p.exists()) {
+            match Self::read(path) {
+                Ok(file) => entries.extend(file.flakes),
+                Err(e) => log::warn!("skipping flake registry: {e:#}"),
+            }
+        }
+        Registry { entries }
+    }
+
+    fn read(path: &Path) -> Result<RegistryFile> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| anyhow!("could not read '{}'", path.display()))?;
+        let file: RegistryFile = serde_json::from_str(&contents)
+            .with_context(|| anyhow!("could not parse '{}'", path.display()))?;
+        if file.version != 2 {
+            bail!("'{}' has unsupported version {}", path.display(), file.version);
+        }
+        Ok(file)
+    }
+
+    /// Resolves an indirect reference to the flake it points at. Other
+    /// references, and indirect references that none of the registries
+    /// list, are returned as-is so that Nix can resolve them through its
+    /// global registry.
+    pub fn resolve(&self, flake_ref: Rc<dyn FlakeRef>) -> Result<Rc<dyn FlakeRef>> {
+        let mut current = flake_ref;
+        for _ in 0..MAX_DEPTH {
+            if current.flake_type() != FlakeType::Indirect {
+                return Ok(current);
+            }
+            let entry = match self.entries.iter().find(|e| e.matches(current.as_ref())) {
+                Some(entry) => entry,
+                None => {
+                    log::debug!("'{}' is not in any local flake registry; leaving it to nix", current.flake_url());
+                    return Ok(current);
+                },
+            };
+            current = entry.apply(current.as_ref())?;
+        }
+        bail!("too many levels of indirection when resolving '{}'", current.flake_url())
+    }
+}
+
+fn user_registry_path() -> Option<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("nix").join("registry.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        let tmp = TempDir::new("registry")?;
+        let workspace = tmp.path().join("workspace.json");
+        let user = tmp.path().join("user.json");
+        std::fs::write(&workspace, r#"{
+            "version": 2,
+            "flakes": [
+                { "from": { "type": "indirect", "id": "nixpkgs" }, "to": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "ref": "nixos-23.11" } },
+                { "from": { "type": "indirect", "id": "tools" }, "to": { "type": "indirect", "id": "shared", "ref": "main" } },
+                { "from": { "type": "indirect", "id": "loop" }, "to": { "type": "indirect", "id": "loop" } }
+            ]
+        }"#)?;
+        std::fs::write(&user, r#"{
+            "version": 2,
+            "flakes": [
+                { "from": { "type": "indirect", "id": "nixpkgs" }, "to": { "type": "github", "owner": "NixOS", "repo": "nixpkgs" } },
+                { "from": { "type": "indirect", "id": "shared" }, "to": { "type": "git", "url": "https://example.com/shared" } },
+                { "from": { "type": "indirect", "id": "pinned" }, "to": { "type": "path", "path": "/src/pinned" }, "exact": true }
+            ]
+        }"#)?;
+        let broken = tmp.path().join("broken.json");
+        let old = tmp.path().join("old.json");
+        std::fs::write(&broken, "{ not json")?;
+        std::fs::write(&old, r#"{ "version": 1, "flakes": [] }"#)?;
+        let registry = Registry::from_files(&[workspace, broken, old, user, tmp.path().join("missing.json")]);
+        let resolve = |url: &str| -> Result<String> {
+            Ok(registry.resolve(crate::flake::parse(url)?)?.flake_url())
+        };
+        assert_eq!(resolve("nixpkgs")?, "github:NixOS/nixpkgs/nixos-23.11");
+        assert_eq!(resolve("flake:nixpkgs/nixos-unstable?dir=lib")?, "github:NixOS/nixpkgs/nixos-unstable?dir=lib");
+        assert_eq!(resolve("flake:tools")?, "git+https://example.com/shared?ref=main");
+        assert_eq!(resolve("flake:pinned")?, "path:/src/pinned");
+        assert_eq!(resolve("github:chadac/nixspace")?, "github:chadac/nixspace");
+        assert_eq!(resolve("flake:pinned/main")?, "flake:pinned/main");
+        assert_eq!(resolve("flake:unknown")?, "flake:unknown");
+        assert!(resolve("flake:loop").is_err());
+        Ok(())
+    }
+}
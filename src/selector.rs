@@ -0,0 +1,131 @@
+use anyhow::{bail, Result};
+use glob_match::glob_match;
+
+use super::config::ProjectConfig;
+
+#[derive(Clone, PartialEq, Debug)]
+enum Term {
+    Tag(String),
+    Name(String),
+}
+
+impl Term {
+    fn parse(expr: &str) -> Result<Self> {
+        let term = match expr.strip_prefix('@') {
+            Some(tag) => Term::Tag(tag.to_string()),
+            None => Term::Name(expr.to_string()),
+        };
+        match &term {
+            Term::Tag(s) | Term::Name(s) if s.is_empty() => bail!("empty project selector: '{expr}'"),
+            _ => Ok(term),
+        }
+    }
+
+    fn matches(&self, project: &ProjectConfig) -> bool {
+        match self {
+            Term::Tag(pattern) => project.tags.as_ref()
+                .map(|tags| tags.iter().any(|t| glob_match(pattern, t)))
+                .unwrap_or(false),
+            Term::Name(pattern) => glob_match(pattern, &project.name),
+        }
+    }
+}
+
+/// Addresses a set of projects by name or tag.
+///
+/// format:
+/// (!)?(@<tag-glob>|<name-glob>)
+///
+/// A project is selected if it matches any of the inclusive terms (or there
+/// are none) and none of the exclusive terms prefixed with `!`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Selector {
+    include: Vec<Term>,
+    exclude: Vec<Term>,
+}
+
+impl Selector {
+    /// Selects every project.
+    pub fn all() -> Self {
+        Selector {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+
+    /// Selects no projects.
+    pub fn none() -> Self {
+        Selector {
+            include: Vec::new(),
+            exclude: vec![Term::Name("*".to_string())],
+        }
+    }
+
+    /// Selects projects by name.
+    pub fn names(names: &[String]) -> Self {
+        Selector {
+            include: names.iter().map(|n| Term::Name(n.to_string())).collect(),
+            exclude: Vec::new(),
+        }
+    }
+
+    pub fn parse<S: AsRef<str>>(exprs: &[S]) -> Result<Self> {
+        let mut selector = Self::all();
+        for expr in exprs {
+            match expr.as_ref().strip_prefix('!') {
+                Some(e) => selector.exclude.push(Term::parse(e)?),
+                None => selector.include.push(Term::parse(expr.as_ref())?),
+            }
+        }
+        Ok(selector)
+    }
+
+    pub fn matches(&self, project: &ProjectConfig) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|t| t.matches(project));
+        included && !self.exclude.iter().any(|t| t.matches(project))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(name: &str, tags: &[&str]) -> ProjectConfig {
+        ProjectConfig {
+            name: name.to_string(),
+            url: format!("github:chadac/{name}"),
+            path: None,
+            environments: None,
+            exclude_environments: None,
+            tags: Some(tags.iter().map(|t| t.to_string()).collect()),
+            strategy: None,
+            versions: None,
+        }
+    }
+
+    fn select<'a>(exprs: &[&str], projects: &'a [ProjectConfig]) -> Result<Vec<&'a str>> {
+        let selector = Selector::parse(exprs)?;
+        Ok(projects.iter().filter(|p| selector.matches(p)).map(|p| p.name.as_str()).collect())
+    }
+
+    #[test]
+    fn test_selects_projects() -> Result<()> {
+        let projects = [
+            project("api", &["backend", "team-payments"]),
+            project("billing", &["backend", "team-payments", "legacy"]),
+            project("web", &["frontend"]),
+            project("web-admin", &["frontend", "legacy"]),
+        ];
+        assert_eq!(select(&[], &projects)?, vec!["api", "billing", "web", "web-admin"]);
+        assert_eq!(select(&["@backend"], &projects)?, vec!["api", "billing"]);
+        assert_eq!(select(&["@team-*"], &projects)?, vec!["api", "billing"]);
+        assert_eq!(select(&["web*"], &projects)?, vec!["web", "web-admin"]);
+        assert_eq!(select(&["@backend", "web"], &projects)?, vec!["api", "billing", "web"]);
+        assert_eq!(select(&["!@legacy"], &projects)?, vec!["api", "web"]);
+        assert_eq!(select(&["@backend", "!billing"], &projects)?, vec!["api"]);
+        assert!(!projects.iter().any(|p| Selector::none().matches(p)));
+        assert!(select(&["@"], &projects).is_err());
+        assert!(select(&["!"], &projects).is_err());
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
-use std::path::{Path, PathBuf};
+use anyhow::{bail, Context, Result};
+use std::cmp::Ordering;
+use std::path::{Component, Path, PathBuf};
 
 pub fn find_root<P: AsRef<Path> + ?Sized>(name: &str, wd: &P) -> Option<PathBuf> {
     let mut cwd: PathBuf = PathBuf::new();
@@ -14,3 +16,175 @@ pub fn find_root<P: AsRef<Path> + ?Sized>(name: &str, wd: &P) -> Option<PathBuf>
     };
     None
 }
+
+/// Normalizes a path relative to the workspace root, removing `.` and
+/// resolving `..` components. Fails if the path is absolute or escapes the
+/// root.
+pub fn normalize_relative_path<P: AsRef<Path> + ?Sized>(path: &P) -> Result<PathBuf> {
+    let path = path.as_ref();
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => normalized.push(c),
+            Component::CurDir => (),
+            Component::ParentDir if normalized.pop() => (),
+            Component::ParentDir => bail!("'{}' is outside of the workspace", path.display()),
+            Component::RootDir | Component::Prefix(_) => {
+                bail!("'{}' must be relative to the workspace root", path.display())
+            },
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        bail!("'{}' refers to the workspace root", path.display());
+    }
+    Ok(normalized)
+}
+
+/// Returns the directory used to cache data shared between workspaces, such
+/// as mirrors of remote repositories.
+pub fn cache_dir() -> Result<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME").context("could not find home directory")?).join(".cache"),
+    };
+    Ok(base.join("nixspace"))
+}
+
+/// Returns the directory in the cache used to mirror a remote repository.
+pub fn mirror_dir(vcs: &str, remote_url: &str) -> Result<PathBuf> {
+    let name: String = remote_url.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    Ok(cache_dir()?.join(vcs).join(name))
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn unix_time() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Returns today's date in UTC as `YYYY-MM-DD`.
+pub fn today() -> String {
+    civil_date(unix_time() / 86400)
+}
+
+/// Converts days since the Unix epoch to a `YYYY-MM-DD` date, using the
+/// algorithm from http://howardhinnant.github.io/date_algorithms.html
+fn civil_date(days: u64) -> String {
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Counts commits in a sentence, i.e. `1 commit` or `3 commits`.
+pub fn commits(count: usize) -> String {
+    match count {
+        1 => "1 commit".to_string(),
+        n => format!("{n} commits"),
+    }
+}
+
+/// Compares two version strings, ordering runs of digits numerically so
+/// that `v1.10` sorts after `v1.9`. A pre-release suffix such as `-rc1`
+/// after the version number sorts before the release itself.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn chunks(s: &str) -> Vec<(bool, &str)> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for (i, c) in s.char_indices().skip(1) {
+            let prev = s[..i].chars().last().unwrap();
+            if prev.is_ascii_digit() != c.is_ascii_digit() {
+                chunks.push((prev.is_ascii_digit(), &s[start..i]));
+                start = i;
+            }
+        }
+        if !s.is_empty() {
+            chunks.push((s[start..].chars().next().unwrap().is_ascii_digit(), &s[start..]));
+        }
+        chunks
+    }
+    fn compare(a: &str, b: &str) -> Ordering {
+        for (x, y) in chunks(a).into_iter().zip(chunks(b)) {
+            let ord = match (x, y) {
+                ((true, x), (true, y)) => {
+                    let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                    x.len().cmp(&y.len()).then(x.cmp(y))
+                },
+                ((_, x), (_, y)) => x.cmp(y),
+            };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        a.len().cmp(&b.len()).then(a.cmp(b))
+    }
+    /// splits `1.0.0-rc1` into `1.0.0` and `rc1`
+    fn pre_release(s: &str) -> (&str, Option<&str>) {
+        let dash = s.char_indices()
+            .find(|(i, c)| *c == '-' && s[..*i].ends_with(|p: char| p.is_ascii_digit()));
+        match dash {
+            Some((i, _)) => (&s[..i], Some(&s[i + 1..])),
+            None => (s, None),
+        }
+    }
+    let ((a, a_pre), (b, b_pre)) = (pre_release(a), pre_release(b));
+    compare(a, b).then_with(|| match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(x), Some(y)) => compare(x, y),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_versions() {
+        let mut versions = vec!["v1.10.0", "v1.9.2", "1.0", "v1.9.10", "v2.0.0-rc1", "v2.0.0", "v1.09.3", "v2.0.0-rc10", "v2.0.0-beta"];
+        versions.sort_by(|a, b| compare_versions(a, b));
+        assert_eq!(versions, vec![
+            "1.0", "v1.9.2", "v1.09.3", "v1.9.10", "v1.10.0", "v2.0.0-beta", "v2.0.0-rc1", "v2.0.0-rc10", "v2.0.0",
+        ]);
+        assert_eq!(compare_versions("release-1.2", "release-1.10"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_commits() {
+        assert_eq!(commits(0), "0 commits");
+        assert_eq!(commits(1), "1 commit");
+        assert_eq!(commits(2), "2 commits");
+    }
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), "1970-01-01");
+        assert_eq!(civil_date(11016), "2000-02-29");
+        assert_eq!(civil_date(19723), "2024-01-01");
+        assert_eq!(civil_date(20453), "2025-12-31");
+    }
+
+    #[test]
+    fn test_normalize_relative_path() -> Result<()> {
+        assert_eq!(normalize_relative_path("lib")?, PathBuf::from("lib"));
+        assert_eq!(normalize_relative_path("./checkouts/../lib/")?, PathBuf::from("lib"));
+        assert_eq!(normalize_relative_path("checkouts/./lib")?, PathBuf::from("checkouts/lib"));
+        assert!(normalize_relative_path("/src/lib").is_err());
+        assert!(normalize_relative_path("../lib").is_err());
+        assert!(normalize_relative_path("checkouts/../../lib").is_err());
+        assert!(normalize_relative_path(".").is_err());
+        assert!(normalize_relative_path("").is_err());
+        Ok(())
+    }
+}
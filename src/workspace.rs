@@ -5,19 +5,27 @@ use std::rc::Rc;
 use colored::Colorize;
 
 use super::flake::FlakeRef;
-use super::lockfile::LockFile;
-use super::config::{Config, LocalConfig, ProjectConfig};
-use super::cli::{CliCommand, Git, Nix};
+use super::lockfile::{FlakeType, LockFile};
+use super::config::{ChangesetConfig, Config, EditBase, LocalConfig, PreviewConfig, ProjectConfig, SignatureCheck, SyncMode, UpdateStrategy, Verification};
+use super::cli::{CliCommand, Git, GitStatus, Hg, Nix, RemoteRefs};
+use super::selector::Selector;
+use super::import::{OnConflict, ProjectImport};
+use super::registry::Registry;
+use super::changelog::Changelog;
+use super::forge::{ChangeRequest, Forge};
+use super::policy::Violation;
 
 static CONFIG_PATH: &str = "nixspace.toml";
 static LOCKFILE_DIR: &str = ".nixspace";
 static LOCAL_PATH: &str = ".nixspace/local.json";
+static ALLOWED_SIGNERS_PATH: &str = ".nixspace/allowed_signers";
 
 pub struct Workspace {
     pub root: PathBuf,
     pub config: Config,
     pub lock: BTreeMap<String, LockFile>,
     pub local: LocalConfig,
+    pub registry: Registry,
 }
 
 #[derive(Clone)]
@@ -25,6 +33,79 @@ pub struct ProjectRef<'config> {
     pub config: &'config ProjectConfig,
     pub flake_ref: Rc<dyn FlakeRef>,
     pub editable: bool,
+    /// path of the checkout linked to the workspace: the active worktree if
+    /// there is one, otherwise the project's path
+    pub checkout: Option<PathBuf>,
+}
+
+/// The result of updating an editable checkout from its upstream branch.
+#[derive(PartialEq, Debug)]
+pub enum SyncOutcome {
+    UpToDate,
+    Updated { from: String, to: String },
+    /// the checkout was left alone, for example because it has uncommitted
+    /// changes
+    Skipped(String),
+    /// the checkout could not be updated without manual intervention
+    Conflict(String),
+}
+
+/// How far an editable project's checkout is from the revision it was
+/// edited at.
+#[derive(Debug)]
+pub struct Drift {
+    pub base: EditBase,
+    /// commits in the checkout that the base revision doesn't have
+    pub ahead: usize,
+    /// commits of the base revision that the checkout doesn't have
+    pub behind: usize,
+}
+
+impl std::fmt::Display for Drift {
+    /// i.e. `3 commits ahead of dev@a3a3dda`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let base = format!("{}@{}", self.base.env, &self.base.rev[..self.base.rev.len().min(7)]);
+        match (self.ahead, self.behind) {
+            (0, 0) => write!(f, "at {base}"),
+            (a, 0) => write!(f, "{} ahead of {base}", crate::util::commits(a)),
+            (0, b) => write!(f, "{} behind {base}", crate::util::commits(b)),
+            (a, b) => write!(f, "{a} ahead, {b} behind {base}"),
+        }
+    }
+}
+
+/// A problem found while verifying a lockfile.
+#[derive(PartialEq, Debug)]
+pub struct LockIssue {
+    /// the lockfile node the problem was found in; for root inputs, the
+    /// name of the project
+    pub node: String,
+    pub problem: String,
+}
+
+/// The state of one project's branch in a changeset.
+pub struct ChangesetEntry {
+    pub project: String,
+    /// branch checked out in the project's checkout
+    pub branch: String,
+    /// commits on the changeset branch since it was started
+    pub commits: usize,
+    pub status: GitStatus,
+    /// commits that haven't been pushed, or `None` if the branch has never
+    /// been pushed
+    pub unpushed: Option<usize>,
+}
+
+/// Name of the environment that pins the projects of a changeset.
+pub fn changeset_env(name: &str) -> String {
+    format!("changeset-{}", name.replace('/', "-"))
+}
+
+/// The remote branch that the workspace is published to.
+pub struct PublishTarget {
+    pub branch: String,
+    pub remote: String,
+    pub remote_ref: String,
 }
 
 fn _config_path(root: &PathBuf) -> PathBuf {
@@ -41,27 +122,30 @@ fn _local_path(root: &PathBuf) -> PathBuf {
 
 impl Workspace {
     pub fn discover() -> Result<Workspace> {
-        let cwd = std::env::current_dir()?;
-        let root = Self::find_root(&cwd).ok_or(anyhow!("Could not find workspace in current directory."))?;
+        Self::discover_from(&std::env::current_dir()?)
+    }
+
+    /// Finds the workspace containing the given directory.
+    pub fn discover_from<P: AsRef<Path> + ?Sized>(cwd: &P) -> Result<Workspace> {
+        let root = Self::find_root(cwd).ok_or(anyhow!("Could not find workspace in current directory."))?;
         Self::at(&root)
     }
 
     pub fn init<P: AsRef<Path> + ?Sized>(root: &P) -> Result<Workspace> {
-        let mut ns_root = PathBuf::new();
-        ns_root.push(root);
+        let ns_root = std::path::absolute(root)?;
         let config = Config::new();
         let envs = config.environments().clone();
         Ok(Workspace {
-            root: ns_root,
             config: Config::new(),
             lock: envs.iter().map(|env| (env.to_string(), LockFile::empty())).collect(),
             local: LocalConfig::new(),
+            registry: Registry::load(&ns_root),
+            root: ns_root,
         })
     }
 
     pub fn at<P: AsRef<Path> + ?Sized>(path: &P) -> Result<Workspace> {
-        let mut root = PathBuf::new();
-        root.push(path);
+        let root = std::path::absolute(path)?;
         let config = Config::read(&_config_path(&root))?;
         let envs = config.environments().clone();
         Ok(Workspace {
@@ -77,6 +161,7 @@ impl Workspace {
                     }
                 }).collect::<Result<BTreeMap<String, LockFile>, _>>()?,
             local: LocalConfig::read(&root.join(LOCAL_PATH))?,
+            registry: Registry::load(&root),
         })
     }
 
@@ -108,7 +193,15 @@ impl Workspace {
         _local_path(&self.root)
     }
 
+    /// Resolves a path from the workspace config against the workspace root,
+    /// regardless of the current directory. Fails for paths outside of the
+    /// workspace.
+    pub fn resolve<P: AsRef<Path> + ?Sized>(&self, path: &P) -> Result<PathBuf> {
+        Ok(self.root.join(crate::util::normalize_relative_path(path)?))
+    }
+
     pub fn save(&self) -> Result<()> {
+        std::fs::create_dir_all(self.root.join(LOCKFILE_DIR))?;
         self.config.write(&self.config_path())?;
         self.local.write(&self.local_path())?;
         for env in self.config.environments() {
@@ -119,31 +212,42 @@ impl Workspace {
         Ok(())
     }
 
-    fn files(&self) -> Vec<PathBuf> {
-        let mut files = Vec::new();
-        let mut flake_nix = self.root.clone();
-        flake_nix.push("flake.nix");
-        let mut flake_lock = self.root.clone();
-        flake_lock.push("flake.lock");
-        files.push(flake_nix);
-        files.push(flake_lock);
-        files.push(self.config_path());
+    /// Paths of the files managed by the workspace, relative to its root:
+    /// the workspace flake, the config, the lockfile of every environment
+    /// and the keys trusted to sign revisions. These are the only files `ns`
+    /// commits.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files = vec![
+            PathBuf::from("flake.nix"),
+            PathBuf::from("flake.lock"),
+            PathBuf::from(CONFIG_PATH),
+        ];
         for env in self.config.environments() {
-            let mut env_lock = self.root.clone();
-            env_lock.push(format!("{env}.lock"));
-            files.push(env_lock);
+            files.push(Path::new(LOCKFILE_DIR).join(format!("{env}.lock")));
+        }
+        if self.allowed_signers().exists() {
+            files.push(PathBuf::from(ALLOWED_SIGNERS_PATH));
         }
         files
     }
 
+    /// Git pathspecs matching the workspace files. Lockfiles of removed
+    /// environments are matched as well, so that their deletion is
+    /// committed.
+    fn pathspecs(&self) -> Vec<String> {
+        let mut specs: Vec<String> = self.files().iter().map(|f| f.to_string_lossy().to_string()).collect();
+        specs.push(format!(":(glob){LOCKFILE_DIR}/*.lock"));
+        specs
+    }
+
+    /// Lists the workspace files with uncommitted changes, relative to the
+    /// root of the workspace's git repository.
+    pub fn changed_files(&self) -> Result<Vec<String>> {
+        Git::changed_files(&self.pathspecs(), &self.root)
+    }
+
     fn changed(&self) -> Result<bool> {
-        let mut changes = Vec::new();
-        for file in self.files() {
-            if Git::changed(&file)? {
-                changes.push(file);
-            }
-        }
-        Ok(!changes.is_empty())
+        Ok(!self.changed_files()?.is_empty())
     }
 
     /// Updates workspace configuration and lockfiles with the latest
@@ -156,18 +260,199 @@ impl Workspace {
         Ok(())
     }
 
-    /// If true, the core files for the workspace are unchanged.
-    pub fn tracks_latest(&self) -> Result<bool> {
-        let items: Result<Vec<bool>, _> = self.files().iter().map(|f| Git::changed(&f)).collect();
-        items?.iter().map(|a| !a).reduce(|a, b| a && b).context("this should never be empty")
+    /// Fetches and updates every editable checkout from its upstream branch,
+    /// updating up to `jobs` checkouts at once.
+    ///
+    /// Checkouts on a branch without an upstream, such as the ones created
+    /// by `ns edit`, follow the ref they were edited from on `origin`.
+    /// Checkouts with uncommitted changes are skipped.
+    pub fn sync_local(&self, mode: SyncMode, jobs: usize) -> Result<Vec<(String, SyncOutcome)>> {
+        // projects sharing a checkout are updated once
+        let mut checkouts: Vec<(Vec<String>, PathBuf, Option<String>)> = Vec::new();
+        for project in self.projects().into_iter().filter(|p| p.editable) {
+            let path = match &project.checkout {
+                Some(p) => self.resolve(p)?,
+                None => continue,
+            };
+            let name = project.config.name.to_string();
+            match checkouts.iter_mut().find(|(_, p, _)| *p == path) {
+                Some((names, _, _)) => names.push(name),
+                None => {
+                    let fallback = self.local.projects.get(&name)
+                        .and_then(|p| p.base.as_ref())
+                        .and_then(|b| b.git_ref.as_ref())
+                        .map(|r| format!("origin/{r}"))
+                        .unwrap_or("origin/HEAD".to_string());
+                    checkouts.push((vec![name], path, Some(fallback)));
+                },
+            }
+        }
+
+        let count = checkouts.len();
+        let queue = std::sync::Mutex::new(checkouts.into_iter().enumerate());
+        let results = std::sync::Mutex::new(Vec::new());
+        std::thread::scope(|scope| {
+            for _ in 0..jobs.clamp(1, count.max(1)) {
+                scope.spawn(|| loop {
+                    let next = queue.lock().unwrap().next();
+                    let (i, (names, path, fallback)) = match next {
+                        Some(n) => n,
+                        None => break,
+                    };
+                    let outcome = sync_checkout(&path, fallback.as_deref(), mode)
+                        .unwrap_or_else(|e| SyncOutcome::Conflict(e.to_string()));
+                    results.lock().unwrap().push((i, names.join(", "), outcome));
+                });
+            }
+        });
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(i, _, _)| *i);
+        Ok(results.into_iter().map(|(_, name, outcome)| (name, outcome)).collect())
+    }
+
+    /// Returns the editable projects whose checkouts have commits on top of
+    /// the revision locked in `env`, with the number of commits.
+    pub fn ahead_of_lock(&self, env: &str) -> Result<Vec<(String, usize)>> {
+        let lock = self.lock.get(env).with_context(|| anyhow!("error: workspace config missing env '{env}'"))?;
+        let mut ahead = Vec::new();
+        for project in self.projects().into_iter().filter(|p| p.editable) {
+            let name = &project.config.name;
+            let path = match &project.checkout {
+                Some(p) => self.resolve(p)?,
+                None => continue,
+            };
+            let rev = match lock.get_input_spec(name).and_then(|spec| spec.rev) {
+                Some(rev) if path.exists() && Git::ref_exists(&rev, &path) => rev,
+                _ => continue,
+            };
+            let commits = Git::count_commits(&format!("{rev}..HEAD"), &path)?;
+            if commits > 0 {
+                ahead.push((name.to_string(), commits));
+            }
+        }
+        Ok(ahead)
+    }
+
+    /// Checks that the workspace can be published, returning where it will
+    /// be pushed to.
+    ///
+    /// The current branch must track a remote branch, which is fetched and
+    /// must not have commits missing locally unless `force` is set. Lockfiles
+    /// may not lock editable projects at local paths or at revisions that
+    /// only exist in a local checkout.
+    pub fn check_publish(&self, force: bool) -> Result<PublishTarget> {
+        let branch = Git::current_branch(&self.root)?;
+        if branch == "HEAD" {
+            bail!("cannot publish from a detached HEAD; check out a branch first");
+        }
+        let (remote, remote_ref) = Git::tracking(&branch, &self.root)?
+            .with_context(|| anyhow!("branch '{branch}' does not track a remote branch; set one with `git push -u <remote> {branch}`"))?;
+        Git::fetch_remote(&remote, &self.root)?;
+        let behind = Git::count_commits(&format!("HEAD..{branch}@{{upstream}}"), &self.root)?;
+        if behind > 0 {
+            if !force {
+                bail!("{remote}/{} has {behind} commits that are not in the workspace; run `ns sync` first, or use --force to overwrite them", remote_ref.trim_start_matches("refs/heads/"));
+            }
+            log::warn!("overwriting {behind} commits on {remote}/{}", remote_ref.trim_start_matches("refs/heads/"));
+        }
+
+        let mut problems = Vec::new();
+        for env in self.config.environments() {
+            let lock = match self.lock.get(&env) {
+                Some(lock) => lock,
+                None => continue,
+            };
+            for project in self.projects_in_env(&env) {
+                let name = &project.config.name;
+                let spec = match lock.get_input_spec(name) {
+                    Some(spec) => spec,
+                    None => continue,
+                };
+                if spec.flake_type == FlakeType::Path {
+                    problems.push(format!("'{env}' locks '{name}' to the local path '{}'", spec.path.unwrap_or_default()));
+                    continue;
+                }
+                let path = match &project.checkout {
+                    Some(p) if project.editable => self.resolve(p)?,
+                    _ => continue,
+                };
+                if let Some(rev) = spec.rev.filter(|rev| path.exists() && Git::ref_exists(rev, &path)) {
+                    if !Git::on_remote(&rev, &path)? {
+                        problems.push(format!("'{env}' locks '{name}' at {}, which has not been pushed", &rev[..rev.len().min(12)]));
+                    }
+                }
+            }
+        }
+        if !problems.is_empty() {
+            bail!("refusing to publish lockfiles that depend on local state:\n  {}", problems.join("\n  "));
+        }
+        Ok(PublishTarget { branch, remote, remote_ref })
     }
 
-    /// pushes any new commits from the workspace
-    pub fn publish(&self, force: bool) -> Result<()> {
-        Git::push(&self.root)?;
+    /// Pushes the current branch of the workspace to its remote branch. With
+    /// `force`, the remote branch is overwritten only if it hasn't changed
+    /// since `check_publish` fetched it.
+    pub fn publish(&self, target: &PublishTarget, force: bool) -> Result<()> {
+        Git::push(&target.remote, &format!("{}:{}", target.branch, target.remote_ref), force, &self.root)?;
         Ok(())
     }
 
+    /// Commits the changed workspace files on a new branch, pushes it to the
+    /// remote of the current branch and opens a review request for it with
+    /// `forge`. The current branch is checked out again afterwards.
+    ///
+    /// The first line of the message is used as the title of the request,
+    /// and the rest as its description. If anything fails, the branch is
+    /// deleted again and the changes are left in the working tree, so that
+    /// the proposal can be retried.
+    pub fn propose(&self, branch: &str, message: &str, forge: &dyn Forge) -> Result<String> {
+        let target = self.check_publish(false)?;
+        if Git::ref_exists(&format!("refs/heads/{branch}"), &self.root)
+            || Git::ref_exists(&format!("refs/remotes/{}/{branch}", target.remote), &self.root) {
+            bail!("branch '{branch}' already exists");
+        }
+        let base = Git::rev_parse("HEAD", &self.root)?;
+        Git::switch(branch, Some("HEAD"), &self.root)?;
+        let mut pushed = false;
+        let result = self.commit(message)
+            .and_then(|_| Git::push(&target.remote, &format!("{branch}:refs/heads/{branch}"), false, &self.root))
+            .and_then(|_| {
+                pushed = true;
+                let (title, description) = message.split_once('\n').unwrap_or((message, ""));
+                forge.open(&ChangeRequest {
+                    title: title.trim().to_string(),
+                    description: description.trim().to_string(),
+                    source_branch: branch.to_string(),
+                    target_branch: target.remote_ref.trim_start_matches("refs/heads/").to_string(),
+                })
+            });
+        let error = match result {
+            Ok(location) => {
+                Git::switch(&target.branch, None, &self.root)?;
+                return Ok(location);
+            },
+            Err(e) => e,
+        };
+        if pushed {
+            if let Err(e) = Git::push_delete(&target.remote, branch, &self.root) {
+                log::warn!("could not delete '{branch}' from {}: {e}", target.remote);
+                Git::switch(&target.branch, None, &self.root)?;
+                return Err(error.context(format!(
+                    "pushed '{branch}' to {}, but could not open a review request for it; open one by hand",
+                    target.remote,
+                )));
+            }
+        }
+        // keep the committed changes, staged, on the original branch
+        Git::reset_soft(&base, &self.root)?;
+        Git::switch(&target.branch, None, &self.root)?;
+        Git::delete_branch(branch, &self.root)?;
+        Err(error.context(match pushed {
+            true => format!("could not open a review request for '{branch}'; it was deleted from {} again", target.remote),
+            false => format!("could not push '{branch}'"),
+        }))
+    }
+
     pub fn project(&self, name: &str) -> Result<ProjectRef> {
         ProjectRef::find(self, name)
     }
@@ -180,29 +465,174 @@ impl Workspace {
                     config: project,
                     flake_ref: project.flake_ref().unwrap(),
                     editable: self.local.is_editable(&project.name),
+                    checkout: self.local.active_worktree(&project.name).or(project.path.as_ref()).cloned(),
                 }
             );
         }
         projects
     }
 
+    /// Returns all projects that are members of an environment.
+    pub fn projects_in_env(&self, env: &str) -> Vec<ProjectRef<'_>> {
+        self.projects().into_iter().filter(|p| p.config.in_env(env)).collect()
+    }
+
+    /// Returns all projects matching a selector.
+    pub fn select(&self, selector: &Selector) -> Vec<ProjectRef<'_>> {
+        self.projects().into_iter().filter(|p| selector.matches(p.config)).collect()
+    }
+
+    /// Returns the names of all projects matching a list of selector
+    /// expressions, failing if none match.
+    pub fn select_names<S: AsRef<str>>(&self, exprs: &[S]) -> Result<Vec<String>> {
+        let selector = Selector::parse(exprs)?;
+        let names: Vec<String> = self.select(&selector).iter()
+            .map(|p| p.config.name.to_string())
+            .collect();
+        if names.is_empty() {
+            bail!("no projects matched the selector");
+        }
+        Ok(names)
+    }
+
+    /// Registers a project. Without a path, a project that lives in the same
+    /// repository as an existing project shares its checkout.
     pub fn register(&mut self, name: &str, flake_ref: Rc<dyn FlakeRef>, path: &Option<String>) -> Result<ProjectRef> {
-        let config = self.config.add_project(name, flake_ref.as_ref(), path)?;
+        let path = match path {
+            Some(p) => Some(PathBuf::from(p)),
+            None => self.shared_checkout(flake_ref.as_ref()),
+        };
+        self.enforce_policy(name, flake_ref.clone())?;
+        let config = self.config.add_project(name, flake_ref.as_ref(), &path)?;
         self.local.unmark_editable(name);
         Ok(ProjectRef {
             config: config,
             flake_ref: flake_ref.clone(),
             editable: false,
+            checkout: config.path.clone(),
         })
     }
 
-    pub fn deregister(&mut self, name: &str, delete: bool) -> Result<()> {
+    /// Checks a project's source against the policy in nixspace.toml.
+    /// Indirect references are checked both as written and as resolved
+    /// through the registries.
+    fn policy_violation(&self, name: &str, flake_ref: Rc<dyn FlakeRef>) -> Option<Violation> {
+        let resolved = self.registry.resolve(flake_ref.clone()).ok();
+        [Some(flake_ref), resolved].into_iter().flatten()
+            .find_map(|r| Some(Violation {
+                input: name.to_string(),
+                url: r.flake_url(),
+                reason: self.config.policy.check(r.as_ref())?,
+            }))
+    }
+
+    fn enforce_policy(&self, name: &str, flake_ref: Rc<dyn FlakeRef>) -> Result<()> {
+        match self.policy_violation(name, flake_ref) {
+            Some(violation) => bail!("the workspace policy does not allow {violation}"),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks every registered project, and every input in the lockfiles of
+    /// the given environments, against the policy in nixspace.toml.
+    pub fn check_policy(&self, envs: &[String]) -> Result<Vec<(Option<String>, Violation)>> {
+        let mut violations: Vec<(Option<String>, Violation)> = self.projects().into_iter()
+            .filter_map(|p| self.policy_violation(&p.config.name, p.flake_ref.clone()))
+            .map(|v| (None, v))
+            .collect();
+        for env in envs {
+            let violations_in_env = self.config.policy.check_lock(self.env_lock(env)?);
+            violations.extend(violations_in_env.into_iter().map(|v| (Some(env.to_string()), v)));
+        }
+        Ok(violations)
+    }
+
+    /// Returns the checkout of an existing project in the same repository.
+    fn shared_checkout(&self, flake_ref: &dyn FlakeRef) -> Option<PathBuf> {
+        let repository = flake_ref.repository()?;
+        self.projects().into_iter()
+            .filter(|p| p.flake_ref.repository().as_ref() == Some(&repository))
+            .find_map(|p| p.config.path.clone())
+    }
+
+    /// Returns the other projects that are checked out at the same path as
+    /// the given project.
+    pub fn siblings(&self, name: &str) -> Result<Vec<ProjectRef<'_>>> {
+        let project = self.project(name)?;
+        Ok(self.projects().into_iter()
+            .filter(|p| p.config.name != name && p.config.path.is_some() && p.config.path == project.config.path)
+            .collect())
+    }
+
+    /// Registers an existing local checkout as an editable project, deriving
+    /// its flake reference from the checkout's git remote.
+    pub fn register_from_path(&mut self, dir: &Path, name: &Option<String>) -> Result<ProjectRef<'_>> {
+        let import = crate::import::inspect_checkout(&self.root, dir, name)?;
+        if self.config.project(&import.name).is_ok() {
+            bail!("project '{}' is already registered", import.name);
+        }
+        self.register_import(&import)?;
+        self.project(&import.name)
+    }
+
+    fn register_import(&mut self, import: &ProjectImport) -> Result<()> {
+        self.enforce_policy(&import.name, import.flake_ref.clone())?;
+        self.config.add_project(&import.name, import.flake_ref.as_ref(), &import.path)?;
+        if import.editable {
+            self.mark_editable(&import.name);
+        } else {
+            self.unmark_editable(&import.name);
+        }
+        Ok(())
+    }
+
+    /// Registers a batch of discovered projects. Projects whose flake
+    /// reference is already registered are skipped, and name collisions are
+    /// handled according to `on_conflict`.
+    ///
+    /// Returns the projects that were registered, with their final names.
+    pub fn import(&mut self, imports: Vec<ProjectImport>, on_conflict: OnConflict) -> Result<Vec<ProjectImport>> {
+        let mut registered = Vec::new();
+        for mut import in imports {
+            if let Some(existing) = self.config.get_project_by_flake_ref(import.flake_ref.as_ref()) {
+                log::warn!("skipping '{}'; already registered as '{}'", import.name, existing.name);
+                continue;
+            }
+            if self.config.project(&import.name).is_ok() {
+                match on_conflict {
+                    OnConflict::Fail => bail!("project '{}' is already registered; see --on-conflict", import.name),
+                    OnConflict::Skip => {
+                        log::warn!("skipping '{}'; a project with the same name is already registered", import.name);
+                        continue;
+                    },
+                    OnConflict::Rename => {
+                        let name = (2..).map(|i| format!("{}-{i}", import.name))
+                            .find(|n| self.config.project(n).is_err())
+                            .unwrap();
+                        if let Some(path) = &import.path {
+                            if !import.editable {
+                                import.path = Some(path.with_file_name(&name));
+                            }
+                        }
+                        import.name = name;
+                    },
+                }
+            }
+            self.register_import(&import)?;
+            registered.push(import);
+        }
+        Ok(registered)
+    }
+
+    pub fn deregister(&mut self, name: &str, delete: bool, force: bool) -> Result<()> {
         // Remove project locally
         if delete {
             let project = self.project(name)?;
-            if let Some(p) = &project.config.path {
-                log::info!("removing directory '{:?}'", p.clone().into_os_string());
-                std::fs::remove_dir_all(&p)?;
+            if let Some(sibling) = self.siblings(name)?.first() {
+                bail!("cannot delete the checkout of '{name}'; it is shared with '{}'", sibling.config.name);
+            }
+            if project.config.path.is_some() {
+                self.remove_checkout(name, force)?;
             } else {
                 log::warn!("project has no path registered, you may need to manually delete the project");
             }
@@ -224,15 +654,138 @@ impl Workspace {
         Ok(())
     }
 
-    pub fn print_tree(&self) -> () {
-        let mut paths = self.projects().iter()
-            .map(|p| {
-                match p.config.path.as_ref() {
-                    Some(path) => Some((path, p.config.url.to_string(), p.editable)),
-                    None => None,
-                }
-            })
-            .flatten()
+    /// Creates a new environment. If `from` is provided, the new environment
+    /// starts as a copy of that environment's lockfile and project settings.
+    pub fn add_env(&mut self, name: &str, strategy: Option<UpdateStrategy>, from: &Option<String>) -> Result<()> {
+        let (strategy, lock) = match from {
+            Some(f) => {
+                let source = self.config.env(f)?;
+                let lock = self.lock.get(f)
+                    .with_context(|| anyhow!("error: workspace missing lockfile for env '{f}'"))?;
+                (strategy.unwrap_or(source.strategy.clone()), lock.clone())
+            },
+            None => (strategy.unwrap_or(UpdateStrategy::Latest), LockFile::empty()),
+        };
+        self.config.add_env(name, strategy)?;
+        if let Some(f) = from {
+            self.config.copy_env_projects(f, name);
+        }
+        self.lock.insert(name.to_string(), lock);
+        Ok(())
+    }
+
+    /// Removes an environment and deletes its lockfile.
+    pub fn rm_env(&mut self, name: &str) -> Result<()> {
+        self.config.rm_env(name)?;
+        self.lock.remove(name);
+        let path = self.lock_path(name);
+        if path.exists() {
+            log::info!("removing lockfile '{}'", path.display());
+            std::fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Creates a throwaway environment that copies the lockfile of `base`,
+    /// with the projects in `branches` pinned to the head of the given
+    /// branch, or to the given revision. An existing preview of the same name
+    /// is replaced.
+    ///
+    /// Returns the revisions that the projects were pinned to.
+    pub fn preview_env(&mut self, name: &str, base: &Option<String>, branches: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>> {
+        let base = base.clone().unwrap_or(self.config.default_env.to_string());
+        if base == name {
+            bail!("a preview cannot replace its base environment '{base}'");
+        }
+        self.config.env(&base)?;
+        let mut remotes = RemoteRefs::default();
+        let mut pins = BTreeMap::new();
+        for (project, branch) in branches {
+            if !self.config.project(project)?.in_env(&base) {
+                bail!("project '{project}' is not part of environment '{base}'");
+            }
+            pins.insert(project.to_string(), self.branch_head(project, branch, &mut remotes)?);
+        }
+        self.lock_preview(name, &base, &pins)?;
+        Ok(pins)
+    }
+
+    /// Replaces the preview `name` with a frozen copy of `base` in which the
+    /// projects in `pins` are locked to the given revisions.
+    fn lock_preview(&mut self, name: &str, base: &str, pins: &BTreeMap<String, String>) -> Result<()> {
+        if let Ok(env) = self.config.env(name) {
+            if env.preview.is_none() {
+                bail!("environment '{name}' already exists and is not a preview");
+            }
+            self.rm_env(name)?;
+        }
+        self.add_env(name, Some(UpdateStrategy::Freeze), &Some(base.to_string()))?;
+        self.config.env_mut(name)?.preview = Some(PreviewConfig { base: base.to_string(), created: crate::util::unix_time() });
+        self.lock_projects(&Some(name.to_string()), &Selector::none(), pins)
+    }
+
+    /// Resolves the head of a branch on a project's remote. Full revisions
+    /// are returned as-is.
+    fn branch_head(&self, name: &str, branch: &str, remotes: &mut RemoteRefs) -> Result<String> {
+        if crate::flake::is_rev(branch) {
+            return Ok(branch.to_string());
+        }
+        let flake_ref = self.registry.resolve(self.project(name)?.flake_ref.clone())?;
+        let refs = if let Some(url) = flake_ref.git_remote_url() {
+            remotes.get::<Git>(&url)?
+        } else if let Some(url) = flake_ref.hg_remote_url() {
+            remotes.get::<Hg>(&url)?
+        } else {
+            bail!("cannot look up branches of '{name}'; it is not a git or mercurial repository");
+        };
+        let head = format!("refs/heads/{branch}");
+        refs.iter().find(|r| r.git_ref == head).map(|r| r.rev.to_string())
+            .with_context(|| anyhow!("'{name}' has no branch '{branch}'"))
+    }
+
+    /// Removes the preview environments that were created more than `days`
+    /// days ago, returning their names.
+    pub fn gc_previews(&mut self, days: u64) -> Result<Vec<String>> {
+        let cutoff = crate::util::unix_time().saturating_sub(days * 86400);
+        let expired: Vec<String> = self.config.environments.iter()
+            .filter(|env| env.preview.as_ref().is_some_and(|p| p.created < cutoff))
+            .map(|env| env.name.to_string())
+            .collect();
+        for env in &expired {
+            self.rm_env(env)?;
+        }
+        Ok(expired)
+    }
+
+    /// Stages a workspace file so that flakes evaluated from the workspace git
+    /// repository can see it. Workspaces outside of git are left untouched.
+    pub fn stage(&self, path: &Path) {
+        let result = if path.exists() { Git::add(path) } else { Git::rm(path) };
+        if let Err(e) = result {
+            log::warn!("could not stage '{}': {e}", path.display());
+        }
+    }
+
+    /// Renames an environment and moves its lockfile.
+    pub fn rename_env(&mut self, name: &str, new_name: &str) -> Result<()> {
+        self.config.rename_env(name, new_name)?;
+        if let Some(lock) = self.lock.remove(name) {
+            self.lock.insert(new_name.to_string(), lock);
+        }
+        let path = self.lock_path(name);
+        if path.exists() {
+            std::fs::rename(&path, self.lock_path(new_name))?;
+        }
+        Ok(())
+    }
+
+    pub fn print_tree(&self, env: &Option<String>) -> () {
+        let projects = match env {
+            Some(e) => self.projects_in_env(e),
+            None => self.projects(),
+        };
+        let mut paths = projects.iter()
+            .filter_map(|p| p.flake_path().map(|path| (path, p.config.url.to_string(), p.editable)))
             .map(|(path, url, e)| (path.to_string_lossy().to_string(), url, e))
             .collect::<Vec<_>>();
         paths.sort();
@@ -246,40 +799,337 @@ impl Workspace {
     }
 
     /// Uses the local copy of a project for building.
-    pub fn edit(&mut self, name: &str) -> Result<()> {
+    /// Clones a project if needed and links it to the workspace.
+    ///
+    /// By default, the revision locked in the environment is checked out on
+    /// a new local branch `ns/<env>/<short-rev>`. With a branch, that branch
+    /// is checked out instead, and created at the locked revision if it
+    /// doesn't exist. If `worktree` is set, the branch is checked out in a
+    /// new git worktree next to the project's checkout.
+    pub fn edit(&mut self, name: &str, env: &Option<String>, branch: &Option<String>, worktree: bool) -> Result<()> {
+        let env = env.clone().unwrap_or(self.config.default_env.to_string());
         let project = self.project(name)?;
-        if project.config.path.is_none() {
-            bail!("cannot use project with no configured local path. see `ns project --help`");
+        let path = match &project.config.path {
+            Some(p) => self.resolve(p)?,
+            None => bail!("cannot use project with no configured local path. see `ns project --help`"),
+        };
+
+        if self.local.is_editable(&name) && !worktree {
+            bail!("project {0} is already marked as editable; exiting", project.config.name);
         }
-        let path = project.config.path.as_ref().unwrap();
 
         if !path.exists() {
            Nix::clone(
-               &project.flake_ref.flake_url(),
+               &self.registry.resolve(project.flake_ref.clone())?.flake_url(),
                &path,
                "."
            )?;
         }
 
-        if self.local.is_editable(&name) {
-            bail!("project {0} is already marked as editable; exiting", project.config.name);
+        let lock = self.lock.get(&env).with_context(|| anyhow!("error: workspace config missing env '{env}'"))?;
+        let rev = lock.get_input_spec(name).and_then(|spec| spec.rev);
+        let git_ref = lock.get_original_spec(name).and_then(|spec| spec.git_ref);
+        if let Some(rev) = &rev {
+            if !Git::ref_exists(rev, &path) {
+                Git::fetch(&path)?;
+            }
+        }
+        let branch = match (branch, &rev) {
+            (Some(b), _) => Some(b.to_string()),
+            (None, Some(rev)) => Some(format!("ns/{env}/{}", &rev[..rev.len().min(12)])),
+            (None, None) => {
+                log::warn!("'{name}' is not locked in '{env}'; keeping the current branch");
+                None
+            },
+        };
+        let shared = self.siblings(name)?.iter().any(|p| p.editable);
+        match (&branch, worktree) {
+            (Some(branch), true) => self.add_worktree(name, branch, rev.as_deref())?,
+            (Some(branch), false) if shared => {
+                log::warn!("not switching '{name}' to '{branch}'; its checkout is shared with another editable project");
+            },
+            (Some(branch), false) => {
+                Git::switch(branch, rev.as_deref(), &path)?;
+            },
+            (None, true) => bail!("a branch is required to create a worktree"),
+            (None, false) => (),
         }
 
         self.mark_editable(&name);
+        if let (Some(rev), Some(branch)) = (rev, branch) {
+            self.local.projects.entry(name.to_string()).or_default().base = Some(EditBase {
+                env, rev, git_ref, branch,
+            });
+        }
         Ok(())
     }
 
-    /// Removes a project from being tracked locally
-    pub fn unedit(&mut self, name: &str, delete: bool) -> Result<()> {
-        self.unmark_editable(name);
+    /// Checks out a branch of a project into a git worktree next to its
+    /// checkout, and links the worktree to the workspace.
+    fn add_worktree(&mut self, name: &str, branch: &str, start: Option<&str>) -> Result<()> {
+        let path = self.project(name)?.config.path.clone()
+            .context("cannot use project with no configured local path")?;
+        let exists = self.local.projects.get(name).is_some_and(|p| p.worktrees.contains_key(branch));
+        if !exists {
+            let dir = path.file_name().context("project path has no directory name")?.to_string_lossy();
+            let worktree = path.with_file_name(format!("{dir}@{}", branch.replace('/', "-")));
+            Git::worktree_add(&self.resolve(&worktree)?, branch, start, &self.resolve(&path)?)?;
+            self.local.projects.entry(name.to_string()).or_default()
+                .worktrees.insert(branch.to_string(), worktree);
+        }
+        self.local.projects.entry(name.to_string()).or_default().worktree = Some(branch.to_string());
+        Ok(())
+    }
+
+    /// Counts how far an editable project's checkout has moved from the
+    /// revision it was edited at.
+    pub fn drift(&self, name: &str) -> Result<Option<Drift>> {
+        let project = self.project(name)?;
+        let base = match self.local.projects.get(name).and_then(|p| p.base.as_ref()) {
+            Some(base) => base,
+            None => return Ok(None),
+        };
+        let path = match &project.checkout {
+            Some(p) => self.resolve(p)?,
+            None => return Ok(None),
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(Drift {
+            ahead: Git::count_commits(&format!("{}..HEAD", base.rev), &path)?,
+            behind: Git::count_commits(&format!("HEAD..{}", base.rev), &path)?,
+            base: base.clone(),
+        }))
+    }
+
+    /// Links a different worktree of an editable project to the workspace.
+    /// Switching to the branch of the main checkout unlinks any worktree.
+    pub fn switch(&mut self, name: &str, branch: &str) -> Result<()> {
+        let project = self.project(name)?;
+        if !project.editable {
+            bail!("project '{name}' is not editable; see `ns edit --help`");
+        }
+        let path = self.resolve(project.config.path.as_ref().context("project has no configured local path")?)?;
+        let local = self.local.projects.entry(name.to_string()).or_default();
+        if local.worktrees.contains_key(branch) {
+            local.worktree = Some(branch.to_string());
+        } else if Git::current_branch(&path)? == branch {
+            local.worktree = None;
+        } else {
+            bail!("'{name}' has no worktree for branch '{branch}'; create one with `ns edit {name} --branch {branch} --worktree`");
+        }
+        Ok(())
+    }
+
+    /// Starts a changeset, or adds projects to an existing one, by checking
+    /// out a branch named `name` in every selected editable project. New
+    /// branches start at the commit currently checked out.
+    ///
+    /// Returns the projects that were added to the changeset.
+    pub fn changeset_start(&mut self, name: &str, selector: &Selector, env: &Option<String>) -> Result<Vec<String>> {
+        let env = env.clone().unwrap_or(self.config.default_env.to_string());
+        self.config.env(&env)?;
+        let mut bases = BTreeMap::new();
+        for project in self.select(selector).into_iter().filter(|p| p.editable) {
+            let path = self.checkout_path(&project.config.name)?;
+            if !path.exists() {
+                log::warn!("skipping '{}'; '{}' does not exist", project.config.name, path.display());
+                continue;
+            }
+            let base = Git::rev_parse("HEAD", &path)?;
+            if Git::current_branch(&path)? != name {
+                Git::switch(name, None, &path)?;
+            }
+            bases.insert(project.config.name.to_string(), base);
+        }
+        if bases.is_empty() {
+            bail!("no editable projects selected; see `ns edit --help`");
+        }
+        let changeset = self.local.changesets.entry(name.to_string())
+            .or_insert_with(|| ChangesetConfig { env, projects: BTreeMap::new() });
+        let mut added = Vec::new();
+        for (project, base) in bases {
+            if !changeset.projects.contains_key(&project) {
+                changeset.projects.insert(project.to_string(), base);
+                added.push(project);
+            }
+        }
+        Ok(added)
+    }
+
+    fn changeset(&self, name: &str) -> Result<&ChangesetConfig> {
+        self.local.changesets.get(name)
+            .with_context(|| anyhow!("no changeset named '{name}'; see `ns changeset start --help`"))
+    }
+
+    /// Resolves the checkout that is linked to the workspace for a project.
+    fn checkout_path(&self, name: &str) -> Result<PathBuf> {
+        let project = self.project(name)?;
+        self.resolve(project.checkout.as_ref().with_context(|| anyhow!("project '{name}' has no configured local path"))?)
+    }
+
+    /// Describes the branch of every project in a changeset.
+    pub fn changeset_status(&self, name: &str) -> Result<Vec<ChangesetEntry>> {
+        let mut entries = Vec::new();
+        for (project, base) in &self.changeset(name)?.projects {
+            let path = self.checkout_path(project)?;
+            let upstream = Git::tracking(name, &path)?
+                .map(|(remote, remote_ref)| format!("refs/remotes/{remote}/{}", remote_ref.trim_start_matches("refs/heads/")))
+                .filter(|upstream| Git::ref_exists(upstream, &path));
+            entries.push(ChangesetEntry {
+                project: project.to_string(),
+                branch: Git::current_branch(&path)?,
+                commits: Git::count_commits(&format!("{base}..refs/heads/{name}"), &path)?,
+                status: Git::status(&path)?,
+                unpushed: match upstream {
+                    Some(upstream) => Some(Git::count_commits(&format!("{upstream}..refs/heads/{name}"), &path)?),
+                    None => None,
+                },
+            });
+        }
+        Ok(entries)
+    }
+
+    pub fn print_changeset(&self, name: &str) -> Result<()> {
+        for entry in self.changeset_status(name)? {
+            let branch = match entry.branch == name {
+                true => format!("on {}", entry.branch).normal(),
+                false => format!("on {}", entry.branch).red(),
+            };
+            let state = match (entry.status.changed, entry.status.untracked) {
+                (0, 0) => "clean".green(),
+                (c, u) => format!("{c} changed, {u} untracked").yellow(),
+            };
+            let pushed = match entry.unpushed {
+                None => "not pushed".yellow(),
+                Some(0) => "pushed".green(),
+                Some(n) => format!("{n} unpushed").yellow(),
+            };
+            println!(
+                "{:030} {} {:12} {} {}",
+                entry.project.bold(), branch, format!("{} commits", entry.commits), state, pushed,
+            );
+        }
+        Ok(())
+    }
+
+    /// Pushes the branch of every project in a changeset to the remote it
+    /// tracks, or `origin`. Returns the commit each branch is at.
+    pub fn changeset_push(&self, name: &str) -> Result<BTreeMap<String, String>> {
+        let mut heads = BTreeMap::new();
+        for project in self.changeset(name)?.projects.keys() {
+            let path = self.checkout_path(project)?;
+            let remote = Git::tracking(name, &path)?
+                .map(|(remote, _)| remote)
+                .unwrap_or("origin".to_string());
+            Git::push_upstream(&remote, name, &path)?;
+            heads.insert(project.to_string(), Git::rev_parse(&format!("refs/heads/{name}"), &path)?);
+        }
+        Ok(heads)
+    }
 
+    /// Writes the lockfile of a changeset's environment as a preview of the
+    /// changeset's base environment. The projects in `heads` are pinned to
+    /// the given commits, and all other projects keep their revisions in the
+    /// base environment.
+    ///
+    /// Returns the name of the environment.
+    pub fn lock_changeset(&mut self, name: &str, heads: &BTreeMap<String, String>) -> Result<String> {
+        let env = changeset_env(name);
+        let base = self.changeset(name)?.env.to_string();
+        self.lock_preview(&env, &base, heads)?;
+        Ok(env)
+    }
+
+    /// Removes a project from being tracked locally
+    pub fn unedit(&mut self, name: &str, delete: bool, force: bool) -> Result<()> {
         if delete {
             let project = self.project(name)?;
-            if let Some(p) = &project.config.path {
-                std::fs::remove_dir_all(&p)?;
+            if let Some(sibling) = self.siblings(name)?.first() {
+                bail!("cannot delete the checkout of '{name}'; it is shared with '{}'", sibling.config.name);
+            }
+            if project.config.path.is_some() {
+                self.remove_checkout(name, force)?;
+            }
+        } else {
+            match self.drift(name) {
+                Ok(Some(drift)) if drift.ahead > 0 || drift.behind > 0 => {
+                    log::warn!("'{name}' is {drift}; the workspace will use the locked revision again");
+                },
+                Err(e) => log::debug!("could not compare '{name}' to its locked revision: {e}"),
+                _ => (),
+            }
+        }
+        self.unmark_editable(name);
+        Ok(())
+    }
+
+    /// Deletes a project's checkout and its worktrees.
+    ///
+    /// Refuses unless the checkout is a repository of the project inside the
+    /// workspace. Unless `force` is set, it also refuses if any work would be
+    /// lost: uncommitted changes, untracked files, stashes or commits that
+    /// haven't been pushed to a remote.
+    fn remove_checkout(&mut self, name: &str, force: bool) -> Result<()> {
+        let project = self.project(name)?;
+        let path = self.resolve(project.config.path.as_ref().context("project has no configured local path")?)?;
+        if !path.exists() {
+            log::info!("'{}' does not exist; nothing to delete", path.display());
+            return Ok(());
+        }
+        let root = std::fs::canonicalize(&self.root)?;
+        let path = std::fs::canonicalize(&path)?;
+        if path == root || !path.starts_with(&root) {
+            bail!("refusing to delete '{}'; it is not inside the workspace", path.display());
+        }
+        if std::fs::canonicalize(Git::toplevel(&path)?)? != path {
+            bail!("refusing to delete '{}'; it is not the root of a git repository", path.display());
+        }
+        let repository = self.registry.resolve(project.flake_ref.clone())?.repository();
+        let remote = Git::remote_url(&path).ok()
+            .and_then(|url| crate::flake::from_git_remote(&url, None).ok())
+            .and_then(|f| f.repository());
+        if repository.is_none() || repository != remote {
+            bail!("refusing to delete '{}'; its remote does not match '{}'", path.display(), project.config.url);
+        }
+
+        let worktrees = self.local.projects.get(name)
+            .map(|p| p.worktrees.values().map(|w| self.resolve(w)).collect::<Result<Vec<_>>>())
+            .transpose()?
+            .unwrap_or_default();
+        if !force {
+            let mut problems = Vec::new();
+            for checkout in std::iter::once(&path).chain(worktrees.iter().filter(|w| w.exists())) {
+                let status = Git::status(checkout)?;
+                if status.changed > 0 {
+                    problems.push(format!("{} uncommitted changes in '{}'", status.changed, checkout.display()));
+                }
+                if status.untracked > 0 {
+                    problems.push(format!("{} untracked files in '{}'", status.untracked, checkout.display()));
+                }
+            }
+            let stashes = Git::stash_count(&path)?;
+            if stashes > 0 {
+                problems.push(format!("{stashes} stashes"));
+            }
+            let unpushed = Git::unpushed_count(&path)?;
+            if unpushed > 0 {
+                problems.push(format!("{unpushed} commits that are not on any remote"));
+            }
+            if !problems.is_empty() {
+                bail!("refusing to delete the checkout of '{name}', which has {}. use --force to delete it anyway", problems.join(", "));
             }
         }
 
+        for worktree in worktrees.iter().filter(|w| w.exists()) {
+            Git::worktree_remove(worktree, force, &path)?;
+        }
+        if let Some(local) = self.local.projects.get_mut(name) {
+            local.worktrees.clear();
+        }
+        log::info!("removing directory '{}'", path.display());
+        std::fs::remove_dir_all(&path)?;
         Ok(())
     }
 
@@ -292,67 +1142,382 @@ impl Workspace {
     }
 
     pub fn update_all_projects(&mut self, env: &Option<String>) -> Result<()> {
+        self.update_projects(env, &Selector::all())
+    }
+
+    /// Updates the projects matching the selector, failing if a selector
+    /// other than `Selector::all()` matches no project. All other projects
+    /// stay pinned to the revision in the current lockfile.
+    pub fn update_projects(&mut self, env: &Option<String>, selector: &Selector) -> Result<()> {
+        if *selector != Selector::all() && self.select(selector).is_empty() {
+            bail!("no projects matched the selector");
+        }
+        self.lock_projects(env, selector, &BTreeMap::new())
+    }
+
+    /// Regenerates the lockfile for an environment. Projects in `pins` are
+    /// locked to the given revision, other projects matching the selector
+    /// are updated using their strategy, and the rest stay at the revision
+    /// in the current lockfile.
+    pub fn lock_projects(&mut self, env: &Option<String>, selector: &Selector, pins: &BTreeMap<String, String>) -> Result<()> {
         let e: String = match env {
             Some(v) => v.to_string(),
             None => self.config.default_env.to_string(),
         };
-        self.lock.get(&e).ok_or(
+        let current = self.lock.get(&e).ok_or(
             anyhow!("error: workspace config missing env '{}'", e)
         )?;
 
-        let default = self.config.env(&e)?.strategy.clone();
         let mut lock_updates = BTreeMap::new();
+        let mut remotes = RemoteRefs::default();
+        let verify = self.config.env(&e)?.verify;
+        if verify.is_some() && !self.allowed_signers().exists() {
+            log::warn!("{} does not exist; no SSH key is trusted to sign revisions", self.allowed_signers().display());
+        }
 
-        for project in self.projects() {
-            let strategy = {
-                match &project.config.strategy {
-                    Some(cfg) => cfg.get(&e).unwrap_or(&default),
-                    None => &default,
-                }
+        for project in self.projects_in_env(&e) {
+            let strategy = self.strategy(project.config, &e)?;
+            let locked_rev = current.get_input_spec(&project.config.name).and_then(|spec| spec.rev)
+                .or_else(|| current.get_original_spec(&project.config.name)
+                    .and_then(|spec| spec.url)
+                    .and_then(|url| crate::flake::template_version(&project.flake_ref.arg("url")?, &url)));
+            let signatures = verify.map(|verify| SignatureCheck {
+                verify,
+                allowed_signers: self.allowed_signers(),
+                locked: locked_rev.clone(),
+            });
+            let pinned_rev = match pins.get(&project.config.name) {
+                Some(rev) => Some(rev.to_string()),
+                None if !selector.matches(project.config) => locked_rev,
+                // frozen projects stay at their locked revision or version
+                None if matches!(strategy, UpdateStrategy::Freeze) => locked_rev,
+                None => None,
+            };
+            let flake_ref = self.registry.resolve(project.flake_ref.clone())?;
+            let metadata = match pinned_rev {
+                Some(rev) => {
+                    Nix::flake_metadata(&flake_ref.with_rev(&rev).flake_url())?
+                },
+                _ => strategy.update(flake_ref, &project.config.versions, &mut remotes, signatures.as_ref())?,
             };
-            let metadata = strategy.update(project.flake_ref)?;
             lock_updates.insert(project.config.name.to_string(), metadata);
         }
         let new_lock = LockFile::from_metadata(lock_updates)?;
+        let violations = self.config.policy.check_lock(&new_lock);
+        if !violations.is_empty() {
+            let list: Vec<String> = violations.iter().map(|v| format!("  {v}")).collect();
+            bail!("the workspace policy does not allow some inputs of '{e}':\n{}", list.join("\n"));
+        }
 
         self.lock.insert(e, new_lock);
 
         Ok(())
     }
 
-    /// Creates a commit tracking the config and lockfile.
+    /// File listing the SSH keys trusted to sign the revisions of
+    /// environments that require signatures.
+    pub fn allowed_signers(&self) -> PathBuf {
+        self.root.join(ALLOWED_SIGNERS_PATH)
+    }
+
+    /// Returns the strategy a project is updated with in an environment.
+    fn strategy<'a>(&'a self, project: &'a ProjectConfig, env: &str) -> Result<&'a UpdateStrategy> {
+        let default = &self.config.env(env)?.strategy;
+        Ok(project.strategy.as_ref().and_then(|cfg| cfg.get(env)).unwrap_or(default))
+    }
+
+    /// Checks that the lockfile of an environment could have been produced
+    /// by `ns update`: every locked revision exists on its remote and was
+    /// picked by the project's strategy, every `narHash` matches the
+    /// fetched source, and the nodes match what merging the lockfiles of
+    /// the locked projects yields.
+    pub fn verify_lock(&self, env: &str) -> Result<Vec<LockIssue>> {
+        let mut issues = self.verify_revs(env)?;
+        issues.extend(self.verify_hashes(env)?);
+        issues.extend(self.verify_merge(env)?);
+        Ok(issues)
+    }
+
+    fn env_lock(&self, env: &str) -> Result<&LockFile> {
+        self.config.env(env)?;
+        self.lock.get(env).with_context(|| anyhow!("environment '{env}' has no lockfile; run `ns update {env}`"))
+    }
+
+    /// Checks the locked revision of every git project against a mirror of
+    /// its remote, including its signature if the environment requires one.
+    fn verify_revs(&self, env: &str) -> Result<Vec<LockIssue>> {
+        let lock = self.env_lock(env)?;
+        let verify = self.config.env(env)?.verify;
+        let mut issues = Vec::new();
+        let mut mirrors: BTreeMap<String, PathBuf> = BTreeMap::new();
+        for project in self.projects_in_env(env) {
+            let name = project.config.name.to_string();
+            let mut issue = |problem: String| issues.push(LockIssue { node: name.to_string(), problem });
+            let spec = match lock.get_input_spec(&name) {
+                Some(spec) => spec,
+                None => {
+                    issue("not locked".to_string());
+                    continue;
+                },
+            };
+            if spec.flake_type == FlakeType::Path {
+                issue(format!("locked to the local path {}", spec.path.unwrap_or_default()));
+                continue;
+            }
+            let url = match self.registry.resolve(project.flake_ref.clone())?.git_remote_url() {
+                Some(url) => url,
+                None => continue,
+            };
+            let rev = match spec.rev {
+                Some(rev) => rev,
+                None => {
+                    issue("no revision locked".to_string());
+                    continue;
+                },
+            };
+            let mirror = match mirrors.get(&url) {
+                Some(m) => m.clone(),
+                None => {
+                    let m = Git::mirror(&url).with_context(|| anyhow!("could not fetch the remote of '{name}'"))?;
+                    mirrors.insert(url.to_string(), m.clone());
+                    m
+                },
+            };
+            if !Git::ref_exists(&rev, &mirror) {
+                issue(format!("revision {rev} does not exist on {url}"));
+                continue;
+            }
+            let strategy = self.strategy(project.config, env)?;
+            let head = Git::head_ref(&mirror)?;
+            if !Git::refs_containing(&rev, &mirror)?.iter().any(|r| strategy.follows(r, &head)) {
+                issue(format!("revision {rev} is not on any ref followed by strategy {}", serde_json::to_string(strategy)?));
+            }
+            if let Some(verify) = verify {
+                if !verify.verified(&rev, &self.allowed_signers(), &mirror) {
+                    issue(match verify {
+                        Verification::SignedTag => format!("revision {rev} has no tag signed by a trusted key"),
+                        Verification::SignedCommit => format!("revision {rev} is not signed by a trusted key"),
+                    });
+                }
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Fetches every locked project and compares its hash with the `narHash`
+    /// in the lockfile.
+    fn verify_hashes(&self, env: &str) -> Result<Vec<LockIssue>> {
+        let lock = self.env_lock(env)?;
+        let mut issues = Vec::new();
+        for (name, node) in lock.root_inputs()? {
+            let mut issue = |problem: String| issues.push(LockIssue { node: name.to_string(), problem });
+            let mut spec = match lock.get_input_spec(&node) {
+                Some(spec) if spec.flake_type != FlakeType::Path => spec,
+                _ => continue,
+            };
+            let expected = match spec.nar_hash.take() {
+                Some(hash) => hash,
+                None => {
+                    issue("no narHash locked".to_string());
+                    continue;
+                },
+            };
+            match Nix::flake_prefetch(&spec.to_flake_ref()?.flake_url()) {
+                Ok(fetched) if fetched.hash != expected => {
+                    issue(format!("narHash {expected} does not match the fetched source ({})", fetched.hash));
+                },
+                Ok(_) => (),
+                Err(e) => issue(format!("could not fetch the locked source: {e}")),
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Merges the lockfiles of the locked projects again and compares the
+    /// result with the lockfile.
+    fn verify_merge(&self, env: &str) -> Result<Vec<LockIssue>> {
+        let lock = self.env_lock(env)?;
+        let mut issues = Vec::new();
+        let mut projects = BTreeMap::new();
+        for (name, node) in lock.root_inputs()? {
+            let mut spec = match lock.get_input_spec(&node) {
+                Some(spec) => spec,
+                None => continue,
+            };
+            spec.nar_hash = None;
+            match Nix::flake_metadata(&spec.to_flake_ref()?.flake_url()) {
+                Ok(metadata) => {
+                    projects.insert(name, metadata);
+                },
+                Err(e) => issues.push(LockIssue { node: name, problem: format!("could not read the locked flake: {e}") }),
+            }
+        }
+        if !issues.is_empty() {
+            return Ok(issues);
+        }
+        let expected = LockFile::from_metadata(projects)?;
+        Ok(lock.mismatched_nodes(&expected).into_iter()
+            .map(|node| LockIssue { node, problem: "does not match the merged lockfiles of the locked projects".to_string() })
+            .collect())
+    }
+
+    /// Describes how the lockfile of an environment changed since `old`,
+    /// including the upstream commits of every updated project.
+    pub fn changelog(&self, env: &str, old: &LockFile) -> Result<Changelog> {
+        let new = self.lock.get(env).with_context(|| anyhow!("error: workspace config missing env '{env}'"))?;
+        let mut changelog = Changelog::diff(env, old, new)?;
+        changelog.fetch_commits(|name| {
+            let project = self.project(name).ok()?;
+            self.registry.resolve(project.flake_ref.clone()).ok()?.git_remote_url()
+        });
+        Ok(changelog)
+    }
+
+    /// Returns the projects in an environment that depend on any of the given
+    /// projects.
+    pub fn consumers(&self, env: &str, names: &[String]) -> Result<Vec<String>> {
+        let lock = self.lock.get(env)
+            .with_context(|| anyhow!("error: workspace config missing env '{env}'"))?;
+        let consumers = lock.consumers(&names.iter().cloned().collect())?;
+        Ok(consumers.into_iter().collect())
+    }
+
+    /// Prints the local state of every project matching the selector.
+    pub fn print_status(&self, selector: &Selector) -> Result<()> {
+        match self.changed_files() {
+            Ok(changed) if !changed.is_empty() => {
+                println!("{} {}", "uncommitted workspace changes:".yellow(), changed.join(", "));
+            },
+            Ok(_) => (),
+            Err(e) => log::debug!("could not check the workspace for changes: {e}"),
+        }
+        for project in self.select(selector) {
+            let path = project.checkout.as_ref().map(|p| self.resolve(p)).transpose()?;
+            let state = match &path {
+                None => "no path".dimmed(),
+                Some(p) if !p.exists() => "not cloned".dimmed(),
+                Some(p) if Git::dirty(p)? => "dirty".yellow(),
+                Some(_) => "clean".green(),
+            };
+            let mut notes = Vec::new();
+            if let Some(b) = self.local.projects.get(&project.config.name).and_then(|p| p.worktree.as_ref()) {
+                notes.push(format!("worktree {b}"));
+            }
+            if project.editable {
+                if let Some(drift) = self.drift(&project.config.name)? {
+                    notes.push(drift.to_string());
+                }
+            }
+            let worktree = match notes.is_empty() {
+                true => "".to_string(),
+                false => format!(" ({})", notes.join(", ")),
+            };
+            println!(
+                "{:030} {:10} {}{}",
+                project.config.name.bold(),
+                if project.editable { "editable".green() } else { "locked".normal() },
+                state,
+                worktree.dimmed(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Creates a commit of the changed workspace files. Anything else the
+    /// user has staged is left staged and is not committed.
     pub fn commit(&self, commit_message: &str) -> Result<()> {
-        Git::reset(&self.root)?;
-        Git::add(&self.config_path())?;
-        for env in self.config.environments() {
-            Git::add(&self.lock_path(&env))?;
+        let changed = self.changed_files()?;
+        if changed.is_empty() {
+            log::info!("no workspace changes to commit");
+            return Ok(());
         }
-        Git::commit(commit_message, &self.root)?;
+        Git::commit_paths(commit_message, &changed, &Git::toplevel(&self.root)?)?;
         Ok(())
     }
 
     /// Returns the current project that a user is within.
     pub fn context(&self) -> Result<Option<ProjectRef>> {
-        let cwd = std::env::current_dir()?;
-        for project in self.projects() {
-            if let Some(subpath) = &project.config.path {
-                let path = self.root.join(subpath);
-                if cwd.starts_with(path) {
-                    return Ok(Some(project))
+        self.context_at(&std::env::current_dir()?)
+    }
+
+    /// Returns the project that contains the given directory.
+    pub fn context_at(&self, cwd: &Path) -> Result<Option<ProjectRef<'_>>> {
+        let cwd = std::path::absolute(cwd)?;
+        // projects sharing a checkout are told apart by the deepest match
+        Ok(self.projects().into_iter()
+            .filter_map(|p| p.flake_path().and_then(|path| self.resolve(&path).ok()).map(|path| (path, p)))
+            .filter(|(path, _)| cwd.starts_with(path))
+            .max_by_key(|(path, _)| path.components().count())
+            .map(|(_, p)| p))
+    }
+}
+
+/// Updates a single checkout from its upstream branch, or from `fallback`
+/// if the checked out branch has no upstream.
+fn sync_checkout(path: &Path, fallback: Option<&str>, mode: SyncMode) -> Result<SyncOutcome> {
+    if !path.exists() {
+        return Ok(SyncOutcome::Skipped("not cloned".to_string()));
+    }
+    if Git::status(path)?.changed > 0 {
+        return Ok(SyncOutcome::Skipped("uncommitted changes".to_string()));
+    }
+    Git::fetch(path)?;
+    let upstream = match Git::upstream(path) {
+        Some(u) => u,
+        None => match fallback.filter(|f| Git::ref_exists(f, path)) {
+            Some(f) => f.to_string(),
+            None => return Ok(SyncOutcome::Skipped("no upstream branch".to_string())),
+        },
+    };
+    let before = Git::rev_parse("HEAD", path)?;
+    let result = match mode {
+        SyncMode::FastForward => Git::merge_ff_only(&upstream, path),
+        SyncMode::Rebase => Git::rebase(&upstream, path),
+    };
+    if let Err(e) = result {
+        log::debug!("could not update '{}': {e}", path.display());
+        return Ok(SyncOutcome::Conflict(match mode {
+            SyncMode::FastForward => format!("cannot fast-forward to {upstream}; the branch has local commits"),
+            SyncMode::Rebase => {
+                let files = Git::conflicts(path).unwrap_or_default();
+                Git::rebase_abort(path)?;
+                match files.is_empty() {
+                    true => format!("rebase onto {upstream} failed"),
+                    false => format!("rebase onto {upstream} conflicts in {}", files.join(", ")),
                 }
-            }
-        }
-        Ok(None)
+            },
+        }));
     }
+    let after = Git::rev_parse("HEAD", path)?;
+    Ok(match before == after {
+        true => SyncOutcome::UpToDate,
+        false => SyncOutcome::Updated { from: before, to: after },
+    })
 }
 
 impl<'config> ProjectRef<'config> {
+    /// Subdirectory of the checkout that contains the project's flake, for
+    /// projects that live in a monorepo.
+    pub fn dir(&self) -> Option<String> {
+        self.flake_ref.arg("dir").map(|d| d.trim_matches('/').to_string()).filter(|d| !d.is_empty())
+    }
+
+    /// Path of the project's flake relative to the workspace root.
+    pub fn flake_path(&self) -> Option<PathBuf> {
+        let path = self.checkout.as_ref()?;
+        Some(match self.dir() {
+            Some(dir) => path.join(dir),
+            None => path.clone(),
+        })
+    }
+
     fn find(ws: &'config Workspace, name: &str) -> Result<ProjectRef<'config>> {
         let config = ws.config.project(name)?;
         Ok(ProjectRef {
             config: config,
             flake_ref: config.flake_ref()?,
             editable: ws.local.is_editable(name),
+            checkout: ws.local.active_worktree(name).or(config.path.as_ref()).cloned(),
         })
     }
 }
@@ -361,7 +1526,8 @@ impl<'config> ProjectRef<'config> {
 mod tests {
     use anyhow::Result;
     use tempdir::TempDir;
-    use super::Workspace;
+    use super::{Selector, Workspace};
+    use crate::import::OnConflict;
 
     #[test]
     fn finds_root_works() -> Result<()> {
@@ -385,4 +1551,682 @@ mod tests {
         );
         Ok(())
     }
+
+    fn git(args: &[&str], cwd: &std::path::Path) -> Result<()> {
+        let status = std::process::Command::new("git").args(args).current_dir(cwd).output()?.status;
+        assert!(status.success(), "git {args:?} failed");
+        Ok(())
+    }
+
+    #[test]
+    fn monorepo_projects_share_checkout() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        let mut ws = Workspace::init(tmp.path())?;
+        ws.register("api", crate::flake::parse("github:chadac/mono?dir=services/api")?, &Some("mono".to_string()))?;
+        ws.register("web", crate::flake::parse("git+https://github.com/chadac/mono.git?dir=web")?, &None)?;
+        ws.register("other", crate::flake::parse("github:chadac/other")?, &None)?;
+        assert_eq!(ws.project("web")?.config.path, Some("mono".into()));
+        assert_eq!(ws.project("web")?.flake_path(), Some("mono/web".into()));
+        assert_eq!(ws.project("api")?.flake_path(), Some("mono/services/api".into()));
+        assert_eq!(ws.project("other")?.config.path, None);
+        assert!(ws.deregister("web", true, false).is_err());
+        Ok(())
+    }
+
+    fn commit(message: &str, cwd: &std::path::Path) -> Result<String> {
+        std::fs::write(cwd.join("flake.nix"), format!("{{ outputs = _: {{}}; }} # {message}"))?;
+        git(&["add", "flake.nix"], cwd)?;
+        git(&["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-q", "-m", message], cwd)?;
+        super::Git::rev_parse("HEAD", cwd)
+    }
+
+    #[test]
+    fn edit_checks_out_locked_rev() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        let checkout = tmp.path().join("lib");
+        std::fs::create_dir_all(&checkout)?;
+        git(&["init", "-q", "-b", "main"], &checkout)?;
+        let locked = commit("locked", &checkout)?;
+        commit("latest", &checkout)?;
+
+        let mut ws = Workspace::init(tmp.path())?;
+        ws.register("lib", crate::flake::parse("github:chadac/lib/main")?, &Some("lib".to_string()))?;
+        ws.lock.insert("dev".to_string(), serde_json::from_str(&format!(r#"{{
+            "nodes": {{
+                "root": {{ "inputs": {{ "lib": "lib" }} }},
+                "lib": {{
+                    "locked": {{ "type": "github", "owner": "chadac", "repo": "lib", "rev": "{locked}" }},
+                    "original": {{ "type": "github", "owner": "chadac", "repo": "lib", "ref": "main" }}
+                }}
+            }},
+            "root": "root",
+            "version": 7
+        }}"#))?);
+        ws.edit("lib", &None, &None, false)?;
+        let branch = format!("ns/dev/{}", &locked[..12]);
+        assert_eq!(super::Git::current_branch(&checkout)?, branch);
+        assert_eq!(super::Git::rev_parse("HEAD", &checkout)?, locked);
+        let base = ws.local.projects["lib"].base.clone().unwrap();
+        assert_eq!((base.env.as_str(), base.rev.as_str(), base.git_ref.as_deref()), ("dev", locked.as_str(), Some("main")));
+        let drift = ws.drift("lib")?.unwrap();
+        assert_eq!((drift.ahead, drift.behind), (0, 0));
+        assert_eq!(drift.to_string(), format!("at dev@{}", &locked[..7]));
+
+        commit("local change", &checkout)?;
+        assert_eq!(ws.drift("lib")?.map(|d| d.to_string()), Some(format!("1 commit ahead of dev@{}", &locked[..7])));
+        commit("another change", &checkout)?;
+        let drift = ws.drift("lib")?.unwrap();
+        assert_eq!((drift.ahead, drift.behind), (2, 0));
+        assert_eq!(drift.to_string(), format!("2 commits ahead of dev@{}", &locked[..7]));
+        ws.unedit("lib", false, false)?;
+        assert!(ws.local.projects["lib"].base.is_none());
+
+        ws.edit("lib", &None, &Some("feature".to_string()), false)?;
+        assert_eq!(super::Git::current_branch(&checkout)?, "feature");
+        assert_eq!(super::Git::rev_parse("HEAD", &checkout)?, locked);
+        Ok(())
+    }
+
+    #[test]
+    fn remove_checkout_refuses_unsafe_deletes() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        let remote = tmp.path().join("remotes/lib.git");
+        std::fs::create_dir_all(&remote)?;
+        git(&["init", "-q", "--bare", "-b", "main"], &remote)?;
+        let root = tmp.path().join("ws");
+        std::fs::create_dir_all(&root)?;
+        git(&["clone", "-q", remote.to_str().unwrap(), "lib"], &root)?;
+        let checkout = root.join("lib");
+        git(&["switch", "-q", "-c", "main"], &checkout)?;
+        commit("init", &checkout)?;
+        git(&["push", "-q", "origin", "main"], &checkout)?;
+
+        let mut ws = Workspace::init(&root)?;
+        let url = format!("git+file:{}", remote.display());
+        ws.register("lib", crate::flake::parse(&url)?, &Some("lib".to_string()))?;
+        ws.register("other", crate::flake::parse("github:chadac/other")?, &Some("lib".to_string()))?;
+        assert!(ws.register("outside", crate::flake::parse("github:chadac/outside")?, &Some("../remotes".to_string())).is_err());
+        ws.register("outside", crate::flake::parse("github:chadac/outside")?, &Some("outside".to_string()))?;
+        ws.config.project_mut("outside")?.path = Some("../remotes".into());
+        ws.mark_editable("lib");
+
+        assert!(ws.unedit("lib", true, true).is_err());
+        assert!(ws.deregister("other", true, false).is_err());
+        assert!(checkout.exists());
+        ws.deregister("other", false, false)?;
+
+        std::fs::write(checkout.join("notes.txt"), "wip")?;
+        assert!(ws.unedit("lib", true, false).is_err());
+        std::fs::remove_file(checkout.join("notes.txt"))?;
+        commit("unpushed", &checkout)?;
+        assert!(ws.unedit("lib", true, false).is_err());
+        git(&["push", "-q", "origin", "main"], &checkout)?;
+        std::fs::write(checkout.join("flake.nix"), "{ outputs = _: { stashed = true; }; }")?;
+        git(&["stash", "-q"], &checkout)?;
+        assert!(ws.unedit("lib", true, false).is_err());
+        git(&["stash", "drop", "-q"], &checkout)?;
+        assert!(ws.local.is_editable("lib"));
+
+        assert!(ws.unedit("outside", true, true).is_err());
+        assert!(tmp.path().join("remotes/lib.git").exists());
+        ws.register("mistyped", crate::flake::parse("github:chadac/mistyped")?, &Some("lib".to_string()))?;
+        ws.mark_editable("mistyped");
+        ws.config.project_mut("lib")?.path = Some("elsewhere".into());
+        let err = ws.unedit("mistyped", true, true).err().unwrap();
+        assert!(err.to_string().contains("its remote does not match"), "{err}");
+        assert!(checkout.exists());
+        ws.deregister("mistyped", false, false)?;
+        ws.config.project_mut("lib")?.path = Some("lib".into());
+
+        ws.unedit("lib", true, false)?;
+        assert!(!checkout.exists());
+        assert!(!ws.local.is_editable("lib"));
+        Ok(())
+    }
+
+    #[test]
+    fn sync_local_updates_editable_checkouts() -> Result<()> {
+        use super::{SyncMode, SyncOutcome};
+        let tmp = TempDir::new("workspace")?;
+        let remote = tmp.path().join("remotes/lib.git");
+        let upstream = tmp.path().join("upstream");
+        std::fs::create_dir_all(&remote)?;
+        std::fs::create_dir_all(&upstream)?;
+        git(&["init", "-q", "--bare", "-b", "main"], &remote)?;
+        git(&["clone", "-q", remote.to_str().unwrap(), "."], &upstream)?;
+        git(&["switch", "-q", "-c", "main"], &upstream)?;
+        let locked = commit("init", &upstream)?;
+        git(&["push", "-q", "origin", "main"], &upstream)?;
+
+        let root = tmp.path().join("ws");
+        std::fs::create_dir_all(root.join("app"))?;
+        git(&["clone", "-q", remote.to_str().unwrap(), "lib"], &root)?;
+        git(&["init", "-q", "-b", "main"], &root.join("app"))?;
+        commit("init", &root.join("app"))?;
+        std::fs::write(root.join("app/flake.nix"), "{ outputs = _: { dirty = true; }; }")?;
+
+        let mut ws = Workspace::init(&root)?;
+        ws.register("lib", crate::flake::parse(&format!("git+file:{}", remote.display()))?, &Some("lib".to_string()))?;
+        ws.register("app", crate::flake::parse("github:chadac/app")?, &Some("app".to_string()))?;
+        ws.mark_editable("lib");
+        ws.mark_editable("app");
+        ws.lock.insert("dev".to_string(), serde_json::from_str(&format!(r#"{{
+            "nodes": {{
+                "root": {{ "inputs": {{ "lib": "lib" }} }},
+                "lib": {{
+                    "locked": {{ "type": "git", "url": "file:{}", "rev": "{locked}" }},
+                    "original": {{ "type": "git", "url": "file:{}" }}
+                }}
+            }},
+            "root": "root",
+            "version": 7
+        }}"#, remote.display(), remote.display()))?);
+
+        let latest = commit("latest", &upstream)?;
+        git(&["push", "-q", "origin", "main"], &upstream)?;
+        let outcomes = ws.sync_local(SyncMode::FastForward, 2)?;
+        assert_eq!(outcomes, vec![
+            ("lib".to_string(), SyncOutcome::Updated { from: locked.clone(), to: latest.clone() }),
+            ("app".to_string(), SyncOutcome::Skipped("uncommitted changes".to_string())),
+        ]);
+        assert_eq!(ws.ahead_of_lock("dev")?, vec![("lib".to_string(), 1)]);
+        assert_eq!(ws.sync_local(SyncMode::FastForward, 2)?[0].1, SyncOutcome::UpToDate);
+
+        let local = commit("local", &root.join("lib"))?;
+        commit("diverged", &upstream)?;
+        git(&["push", "-q", "origin", "main"], &upstream)?;
+        assert!(matches!(ws.sync_local(SyncMode::FastForward, 2)?[0].1, SyncOutcome::Conflict(_)));
+        match &ws.sync_local(SyncMode::Rebase, 2)?[0].1 {
+            SyncOutcome::Conflict(reason) => assert!(reason.contains("flake.nix")),
+            outcome => panic!("expected a conflict, got {outcome:?}"),
+        }
+        assert_eq!(super::Git::rev_parse("HEAD", &root.join("lib"))?, local);
+        assert!(!super::Git::dirty(&root.join("lib"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn check_publish_preconditions() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        let remote = tmp.path().join("remotes/ws.git");
+        let lib_remote = tmp.path().join("remotes/lib.git");
+        for r in [&remote, &lib_remote] {
+            std::fs::create_dir_all(r)?;
+            git(&["init", "-q", "--bare", "-b", "main"], r)?;
+        }
+        let root = tmp.path().join("ws");
+        git(&["clone", "-q", remote.to_str().unwrap(), root.to_str().unwrap()], tmp.path())?;
+        git(&["switch", "-q", "-c", "trunk"], &root)?;
+        git(&["clone", "-q", lib_remote.to_str().unwrap(), "lib"], &root)?;
+        let lib = root.join("lib");
+        git(&["switch", "-q", "-c", "main"], &lib)?;
+        let pushed = commit("init", &lib)?;
+        git(&["push", "-q", "origin", "main"], &lib)?;
+        let unpushed = commit("local", &lib)?;
+
+        let mut ws = Workspace::init(&root)?;
+        let url = format!("git+file:{}", lib_remote.display());
+        ws.register("lib", crate::flake::parse(&url)?, &Some("lib".to_string()))?;
+        ws.mark_editable("lib");
+        let lock_at = |rev: &str| -> Result<crate::lockfile::LockFile> {
+            Ok(serde_json::from_str(&format!(r#"{{
+                "nodes": {{
+                    "root": {{ "inputs": {{ "lib": "lib" }} }},
+                    "lib": {{
+                        "locked": {{ "type": "git", "url": "file:{0}", "rev": "{rev}" }},
+                        "original": {{ "type": "git", "url": "file:{0}" }}
+                    }}
+                }},
+                "root": "root",
+                "version": 7
+            }}"#, lib_remote.display()))?)
+        };
+        ws.lock.insert("dev".to_string(), lock_at(&pushed)?);
+        ws.save()?;
+        std::fs::write(root.join(".gitignore"), "lib\n")?;
+        git(&["add", "."], &root)?;
+        git(&["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-q", "-m", "init"], &root)?;
+        // trunk has no upstream yet
+        assert!(ws.check_publish(false).is_err());
+        git(&["push", "-q", "-u", "origin", "trunk:release"], &root)?;
+
+        let target = ws.check_publish(false)?;
+        assert_eq!((target.remote.as_str(), target.remote_ref.as_str()), ("origin", "refs/heads/release"));
+
+        let other = tmp.path().join("other");
+        git(&["clone", "-q", "-b", "release", remote.to_str().unwrap(), other.to_str().unwrap()], tmp.path())?;
+        commit("remote change", &other)?;
+        git(&["push", "-q", "origin", "release"], &other)?;
+        assert!(ws.check_publish(false).is_err());
+        let target = ws.check_publish(true)?;
+        commit("local change", &root)?;
+        ws.publish(&target, true)?;
+        assert_eq!(super::Git::rev_parse("origin/release", &root)?, super::Git::rev_parse("HEAD", &root)?);
+
+        ws.lock.insert("dev".to_string(), lock_at(&unpushed)?);
+        let err = ws.check_publish(false).err().unwrap().to_string();
+        assert!(err.contains("has not been pushed"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn commit_only_touches_workspace_files() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        git(&["init", "-q", "-b", "main"], tmp.path())?;
+        git(&["config", "user.name", "test"], tmp.path())?;
+        git(&["config", "user.email", "test@example.com"], tmp.path())?;
+        let mut ws = Workspace::init(tmp.path())?;
+        ws.add_env("prod", None, &None)?;
+        ws.add_env("my env", None, &None)?;
+        ws.save()?;
+        std::fs::write(tmp.path().join("flake.nix"), "{ outputs = _: {}; }")?;
+        std::fs::write(tmp.path().join("notes.txt"), "todo")?;
+        assert_eq!(ws.changed_files()?, vec![".nixspace/dev.lock", ".nixspace/my env.lock", ".nixspace/prod.lock", "flake.nix", "nixspace.toml"]);
+        ws.commit("initial commit")?;
+        assert!(ws.changed_files()?.is_empty());
+
+        git(&["mv", "flake.nix", "renamed flake.nix"], tmp.path())?;
+        assert_eq!(super::Git::changed_files(&["*.nix".to_string()], tmp.path())?, vec!["renamed flake.nix", "flake.nix"]);
+        git(&["mv", "renamed flake.nix", "flake.nix"], tmp.path())?;
+        git(&["add", "notes.txt"], tmp.path())?;
+        ws.rm_env("prod")?;
+        ws.save()?;
+        ws.commit("remove prod")?;
+        let out = std::process::Command::new("git").args(["show", "--name-status", "--format=", "HEAD"])
+            .current_dir(tmp.path()).output()?;
+        assert_eq!(String::from_utf8(out.stdout)?.trim(), "D\t.nixspace/prod.lock\nM\tnixspace.toml");
+        let out = std::process::Command::new("git").args(["diff", "--cached", "--name-only"])
+            .current_dir(tmp.path()).output()?;
+        assert_eq!(String::from_utf8(out.stdout)?.trim(), "notes.txt");
+        Ok(())
+    }
+
+    #[test]
+    fn propose_pushes_a_branch_for_review() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        let remote = tmp.path().join("remotes/ws.git");
+        std::fs::create_dir_all(&remote)?;
+        git(&["init", "-q", "--bare", "-b", "main"], &remote)?;
+        let root = tmp.path().join("ws");
+        git(&["clone", "-q", remote.to_str().unwrap(), root.to_str().unwrap()], tmp.path())?;
+        git(&["switch", "-q", "-c", "main"], &root)?;
+        git(&["config", "user.name", "test"], &root)?;
+        git(&["config", "user.email", "test@example.com"], &root)?;
+        let mut ws = Workspace::init(&root)?;
+        ws.save()?;
+        git(&["add", "."], &root)?;
+        commit("init", &root)?;
+        git(&["push", "-q", "-u", "origin", "main"], &root)?;
+
+        ws.add_env("prod", None, &None)?;
+        ws.save()?;
+        let (url, server) = crate::forge::tests::serve_once("422 Unprocessable Entity", r#"{ "error": "invalid" }"#)?;
+        let forge = crate::forge::HttpForge { url, token_env: None };
+        let err = ws.propose("ns/update-prod", "update 2 projects in prod\n\n* a: 1 → 2\n", &forge).err().unwrap();
+        assert!(format!("{err:#}").contains("HTTP 422"), "{err:#}");
+        server.join().unwrap();
+        assert_eq!(super::Git::current_branch(&root)?, "main");
+        assert!(!super::Git::ref_exists("refs/heads/ns/update-prod", &root));
+        assert!(!super::Git::ref_exists("refs/heads/ns/update-prod", &remote));
+        assert!(root.join(".nixspace/prod.lock").exists());
+
+        let (url, server) = crate::forge::tests::serve_once("201 Created", r#"{ "url": "http://forge.test/1" }"#)?;
+        let forge = crate::forge::HttpForge { url, token_env: None };
+        let location = ws.propose("ns/update-prod", "update 2 projects in prod\n\n* a: 1 → 2\n", &forge)?;
+        assert_eq!(location, "http://forge.test/1");
+        let received = server.join().unwrap();
+        let body: serde_json::Value = serde_json::from_str(received.split_once("\r\n\r\n").unwrap().1)?;
+        assert_eq!(body["title"], "update 2 projects in prod");
+        assert_eq!(body["description"], "* a: 1 → 2");
+        assert_eq!(body["target_branch"], "main");
+
+        assert_eq!(super::Git::current_branch(&root)?, "main");
+        assert!(!root.join(".nixspace/prod.lock").exists());
+        let out = std::process::Command::new("git").args(["ls-tree", "--name-only", "-r", "origin/ns/update-prod"])
+            .current_dir(&root).output()?;
+        assert!(String::from_utf8(out.stdout)?.contains(".nixspace/prod.lock"));
+        assert!(ws.propose("ns/update-prod", "again", &crate::forge::GitForge {}).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn changesets_span_editable_projects() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        let root = tmp.path().join("ws");
+        let mut ws = Workspace::init(&root)?;
+        for name in ["lib", "app", "docs"] {
+            let remote = tmp.path().join(format!("remotes/{name}.git"));
+            std::fs::create_dir_all(&remote)?;
+            git(&["init", "-q", "--bare", "-b", "main"], &remote)?;
+            std::fs::create_dir_all(&root)?;
+            git(&["clone", "-q", remote.to_str().unwrap(), name], &root)?;
+            git(&["switch", "-q", "-c", "main"], &root.join(name))?;
+            commit("init", &root.join(name))?;
+            git(&["push", "-q", "origin", "main"], &root.join(name))?;
+            let url = format!("git+file:{}", remote.display());
+            ws.register(name, crate::flake::parse(&url)?, &Some(name.to_string()))?;
+        }
+        ws.mark_editable("lib");
+        ws.mark_editable("app");
+
+        assert_eq!(ws.changeset_start("feat/x", &Selector::names(&["lib".to_string()]), &None)?, vec!["lib"]);
+        assert_eq!(ws.changeset_start("feat/x", &Selector::all(), &None)?, vec!["app"]);
+        assert!(ws.changeset_start("feat/y", &Selector::names(&["docs".to_string()]), &None).is_err());
+        assert_eq!(super::Git::current_branch(&root.join("lib"))?, "feat/x");
+        assert_eq!(super::Git::current_branch(&root.join("docs"))?, "main");
+
+        commit("feature", &root.join("lib"))?;
+        std::fs::write(root.join("app/flake.nix"), "{ outputs = _: { wip = true; }; }")?;
+        let status = ws.changeset_status("feat/x")?;
+        assert_eq!(status.iter().map(|e| (e.project.as_str(), e.commits, e.status.changed, e.unpushed)).collect::<Vec<_>>(),
+                   vec![("app", 0, 1, None), ("lib", 1, 0, None)]);
+
+        let heads = ws.changeset_push("feat/x")?;
+        assert_eq!(heads["lib"], super::Git::rev_parse("HEAD", &root.join("lib"))?);
+        assert!(super::Git::ref_exists("refs/heads/feat/x", &tmp.path().join("remotes/lib.git")));
+        assert!(ws.changeset_status("feat/x")?.iter().all(|e| e.unpushed == Some(0)));
+        assert_eq!(super::changeset_env("feat/x"), "changeset-feat-x");
+
+        // locking needs nix for every project in the environment, so lock a
+        // changeset based on an environment without any
+        ws.add_env("stage", None, &None)?;
+        for name in ["lib", "app", "docs"] {
+            ws.config.project_mut(name)?.exclude_environments = Some(vec!["stage".to_string()]);
+        }
+        ws.changeset_start("feat/y", &Selector::names(&["lib".to_string()]), &Some("stage".to_string()))?;
+        assert_eq!(ws.lock_changeset("feat/y", &std::collections::BTreeMap::new())?, "changeset-feat-y");
+        let env = ws.config.env("changeset-feat-y")?;
+        assert!(matches!(env.strategy, crate::config::UpdateStrategy::Freeze));
+        assert_eq!(env.preview.as_ref().map(|p| p.base.as_str()), Some("stage"));
+        assert!(ws.lock.contains_key("changeset-feat-y"));
+        assert!(!ws.config.project("lib")?.in_env("changeset-feat-y"));
+        ws.lock_changeset("feat/y", &std::collections::BTreeMap::new())?;
+        ws.config.env_mut("changeset-feat-y")?.preview.as_mut().unwrap().created -= 8 * 86400;
+        assert_eq!(ws.gc_previews(7)?, vec!["changeset-feat-y"]);
+        Ok(())
+    }
+
+    #[test]
+    fn previews_resolve_branches_and_expire() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        let remote = tmp.path().join("remotes/lib.git");
+        let upstream = tmp.path().join("upstream");
+        std::fs::create_dir_all(&remote)?;
+        std::fs::create_dir_all(&upstream)?;
+        git(&["init", "-q", "--bare", "-b", "main"], &remote)?;
+        git(&["clone", "-q", remote.to_str().unwrap(), "."], &upstream)?;
+        git(&["switch", "-q", "-c", "feature-x"], &upstream)?;
+        let head = commit("feature", &upstream)?;
+        git(&["push", "-q", "origin", "feature-x"], &upstream)?;
+
+        let mut ws = Workspace::init(tmp.path())?;
+        ws.register("lib", crate::flake::parse(&format!("git+file:{}", remote.display()))?, &None)?;
+        let mut remotes = crate::cli::RemoteRefs::default();
+        assert_eq!(ws.branch_head("lib", "feature-x", &mut remotes)?, head);
+        assert_eq!(ws.branch_head("lib", &"a".repeat(40), &mut remotes)?, "a".repeat(40));
+        assert!(ws.branch_head("lib", "missing", &mut remotes).is_err());
+        let branches = std::collections::BTreeMap::from([("lib".to_string(), "feature-x".to_string())]);
+        assert!(ws.preview_env("dev", &None, &branches).is_err());
+
+        let now = crate::util::unix_time();
+        for (name, age) in [("old", 10), ("recent", 1)] {
+            ws.add_env(name, None, &None)?;
+            ws.config.env_mut(name)?.preview = Some(crate::config::PreviewConfig {
+                base: "dev".to_string(),
+                created: now - age * 86400,
+            });
+        }
+        ws.add_env("stage", None, &None)?;
+        assert!(ws.preview_env("stage", &None, &branches).is_err());
+        assert_eq!(ws.gc_previews(7)?, vec!["old"]);
+        assert_eq!(ws.config.environments(), vec!["dev", "recent", "stage"]);
+        Ok(())
+    }
+
+    #[test]
+    fn verify_checks_locked_revisions() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        let remote = tmp.path().join("remotes/lib.git");
+        let upstream = tmp.path().join("upstream");
+        std::fs::create_dir_all(&remote)?;
+        std::fs::create_dir_all(&upstream)?;
+        git(&["init", "-q", "--bare", "-b", "main"], &remote)?;
+        git(&["clone", "-q", remote.to_str().unwrap(), "."], &upstream)?;
+        let release = commit("release", &upstream)?;
+        git(&["tag", "v1.0"], &upstream)?;
+        let main = commit("main", &upstream)?;
+        git(&["switch", "-q", "-c", "feature-x"], &upstream)?;
+        let feature = commit("feature", &upstream)?;
+        git(&["push", "-q", "--tags", "origin", "main", "feature-x"], &upstream)?;
+
+        let mut ws = Workspace::init(tmp.path())?;
+        ws.register("lib", crate::flake::parse(&format!("git+file:{}", remote.display()))?, &None)?;
+        let lock = |ws: &mut Workspace, locked: &str| -> Result<()> {
+            ws.lock.insert("dev".to_string(), serde_json::from_str(&format!(r#"{{
+                "nodes": {{ "root": {{ "inputs": {{ "lib": "lib" }} }}, "lib": {{ "locked": {locked} }} }},
+                "root": "root",
+                "version": 7
+            }}"#))?);
+            Ok(())
+        };
+        let at = |rev: &str| format!(r#"{{ "type": "git", "url": "file:{}", "rev": "{rev}" }}"#, remote.display());
+        let problems = |ws: &Workspace| -> Result<Vec<String>> {
+            Ok(ws.verify_revs("dev")?.into_iter().map(|i| format!("{}: {}", i.node, i.problem)).collect())
+        };
+
+        lock(&mut ws, &at(&main))?;
+        assert_eq!(problems(&ws)?, Vec::<String>::new());
+        lock(&mut ws, &at(&feature))?;
+        assert_eq!(problems(&ws)?, vec![format!(r#"lib: revision {feature} is not on any ref followed by strategy "latest""#)]);
+        lock(&mut ws, &at(&"0".repeat(40)))?;
+        assert!(problems(&ws)?[0].contains("does not exist"));
+        lock(&mut ws, r#"{ "type": "path", "path": "/home/me/lib" }"#)?;
+        assert_eq!(problems(&ws)?, vec!["lib: locked to the local path /home/me/lib"]);
+
+        lock(&mut ws, &at(&release))?;
+        ws.config.env_mut("dev")?.strategy = crate::config::UpdateStrategy::LatestTag(Some("v*".to_string()));
+        assert!(problems(&ws)?.is_empty());
+        lock(&mut ws, &at(&feature))?;
+        assert_eq!(problems(&ws)?.len(), 1);
+        ws.config.env_mut("dev")?.strategy = crate::config::UpdateStrategy::Freeze;
+        assert!(problems(&ws)?.is_empty());
+        ws.config.env_mut("dev")?.verify = Some(crate::config::Verification::SignedTag);
+        assert_eq!(problems(&ws)?, vec![format!("lib: revision {feature} has no tag signed by a trusted key")]);
+        ws.config.env_mut("dev")?.verify = None;
+
+        ws.register("app", crate::flake::parse("github:chadac/app")?, &None)?;
+        assert_eq!(problems(&ws)?, vec!["app: not locked"]);
+        Ok(())
+    }
+
+    #[test]
+    fn policy_restricts_sources() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        let mut ws = Workspace::init(tmp.path())?;
+        ws.register("app", crate::flake::parse("github:chadac/app")?, &None)?;
+        ws.register("local", crate::flake::parse("path:/home/me/local")?, &None)?;
+        ws.config.policy = toml::from_str(r#"
+            allow = ["github.com/chadac/*"]
+            deny = ["path:"]
+        "#)?;
+        assert!(ws.update_projects(&None, &Selector::parse(&["missing", "@none"])?).is_err());
+        let err = ws.register("other", crate::flake::parse("github:someone/other")?, &None).err().unwrap();
+        assert!(err.to_string().contains("other (github:someone/other): not matched by any allowed pattern"), "{err}");
+        assert!(ws.config.project("other").is_err());
+
+        ws.lock.insert("dev".to_string(), serde_json::from_str(r#"{
+            "nodes": {
+                "root": { "inputs": { "app": "app" } },
+                "app": {
+                    "inputs": { "nixpkgs": "app_nixpkgs" },
+                    "locked": { "type": "github", "owner": "chadac", "repo": "app", "rev": "1111111111111111111111111111111111111111" }
+                },
+                "app_nixpkgs": {
+                    "locked": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "2222222222222222222222222222222222222222" }
+                }
+            },
+            "root": "root",
+            "version": 7
+        }"#)?);
+        let violations: Vec<String> = ws.check_policy(&["dev".to_string()])?.into_iter()
+            .map(|(env, v)| format!("{}{}: {}", env.map(|e| format!("{e} ")).unwrap_or_default(), v.input, v.reason))
+            .collect();
+        assert_eq!(violations, vec![
+            "local: denied by 'path:'",
+            "dev app/nixpkgs: not matched by any allowed pattern",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn paths_resolve_from_nested_directories() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        let checkout = tmp.path().join("checkouts/lib");
+        let nested = checkout.join("src/deep");
+        std::fs::create_dir_all(&nested)?;
+        git(&["init", "-q", "-b", "main"], &checkout)?;
+        git(&["remote", "add", "origin", "https://github.com/chadac/lib.git"], &checkout)?;
+        commit("init", &checkout)?;
+
+        let mut ws = Workspace::init(tmp.path())?;
+        ws.register("lib", crate::flake::parse("github:chadac/lib")?, &Some("./checkouts/../checkouts/lib".to_string()))?;
+        assert_eq!(ws.project("lib")?.config.path, Some("checkouts/lib".into()));
+        assert!(ws.register("up", crate::flake::parse("github:chadac/up")?, &Some("../up".to_string())).is_err());
+        assert!(ws.register("abs", crate::flake::parse("github:chadac/abs")?, &Some("/tmp/abs".to_string())).is_err());
+        ws.save()?;
+
+        let mut ws = Workspace::discover_from(&nested)?;
+        assert_eq!(ws.context_at(&nested)?.map(|p| p.config.name.to_string()), Some("lib".to_string()));
+        ws.edit("lib", &None, &Some("feat/x".to_string()), true)?;
+        assert!(tmp.path().join("checkouts/lib@feat-x/flake.nix").exists());
+        assert!(!nested.join("checkouts").exists());
+        ws.unedit("lib", true, true)?;
+        assert!(!checkout.exists());
+        assert!(!tmp.path().join("checkouts/lib@feat-x").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn edit_with_worktrees() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        let checkout = tmp.path().join("checkouts/lib");
+        std::fs::create_dir_all(&checkout)?;
+        git(&["init", "-q", "-b", "main"], &checkout)?;
+        std::fs::write(checkout.join("flake.nix"), "{ outputs = _: {}; }")?;
+        git(&["add", "flake.nix"], &checkout)?;
+        git(&["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-q", "-m", "init"], &checkout)?;
+
+        let mut ws = Workspace::init(tmp.path())?;
+        ws.register("lib", crate::flake::parse("github:chadac/lib")?, &Some("checkouts/lib".to_string()))?;
+        assert!(ws.edit("lib", &None, &None, true).is_err());
+        ws.edit("lib", &None, &Some("feat/x".to_string()), true)?;
+        assert!(tmp.path().join("checkouts/lib@feat-x/flake.nix").exists());
+        assert_eq!(ws.project("lib")?.checkout, Some("checkouts/lib@feat-x".into()));
+        assert_eq!(super::Git::current_branch(&checkout)?, "main");
+
+        ws.switch("lib", "main")?;
+        assert_eq!(ws.project("lib")?.checkout, Some("checkouts/lib".into()));
+        ws.switch("lib", "feat/x")?;
+        assert_eq!(ws.project("lib")?.flake_path(), Some("checkouts/lib@feat-x".into()));
+        assert!(ws.switch("lib", "other").is_err());
+
+        ws.unedit("lib", false, false)?;
+        assert_eq!(ws.project("lib")?.checkout, Some("checkouts/lib".into()));
+        assert!(ws.switch("lib", "feat/x").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn register_from_path_uses_git_remote() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        Workspace::init(tmp.path())?.save()?;
+        let checkout = tmp.path().join("checkouts/project-a");
+        std::fs::create_dir_all(checkout.join("nested"))?;
+        git(&["init", "-q"], &checkout)?;
+        git(&["remote", "add", "origin", "git@github.com:chadac/project-a.git"], &checkout)?;
+        std::fs::write(checkout.join("flake.nix"), "{ outputs = _: {}; }")?;
+        std::fs::write(checkout.join("nested/flake.nix"), "{ outputs = _: {}; }")?;
+
+        let mut ws = Workspace::at(tmp.path())?;
+        ws.register_from_path(&checkout, &None)?;
+        ws.register_from_path(&checkout.join("nested"), &None)?;
+        assert!(ws.register_from_path(&checkout, &None).is_err());
+
+        let project = ws.project("project-a")?;
+        assert_eq!(project.config.url, "git+ssh://git@github.com/chadac/project-a.git");
+        assert_eq!(project.config.path, Some("checkouts/project-a".into()));
+        assert!(project.editable);
+        let nested = ws.project("nested")?;
+        assert_eq!(nested.config.url, "git+ssh://git@github.com/chadac/project-a.git?dir=nested");
+        assert_eq!(nested.config.path, Some("checkouts/project-a".into()));
+        assert_eq!(nested.flake_path(), Some("checkouts/project-a/nested".into()));
+        assert_eq!(ws.siblings("nested")?.iter().map(|p| p.config.name.as_str()).collect::<Vec<_>>(), vec!["project-a"]);
+
+        std::fs::remove_file(checkout.join("flake.nix"))?;
+        assert!(ws.register_from_path(&checkout, &Some("not-a-flake".to_string())).is_err());
+        let outside = TempDir::new("outside")?;
+        std::fs::write(outside.path().join("flake.nix"), "{ outputs = _: {}; }")?;
+        assert!(ws.register_from_path(outside.path(), &Some("outside".to_string())).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn import_handles_conflicts() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        Workspace::init(tmp.path())?.save()?;
+        for (dir, remote) in [("a", "https://github.com/chadac/a"), ("nested/b", "https://github.com/chadac/b"), ("c", "https://github.com/other/a")] {
+            let checkout = tmp.path().join(dir);
+            std::fs::create_dir_all(&checkout)?;
+            git(&["init", "-q"], &checkout)?;
+            git(&["remote", "add", "origin", remote], &checkout)?;
+            std::fs::write(checkout.join("flake.nix"), "{ outputs = _: {}; }")?;
+        }
+        std::fs::write(tmp.path().join("flake.nix"), "{ outputs = _: {}; }")?;
+
+        let mut ws = Workspace::at(tmp.path())?;
+        let scanned = crate::import::scan(&ws.root, &ws.root)?;
+        let names: Vec<_> = scanned.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "a", "b"]);
+        assert!(ws.import(scanned, OnConflict::Fail).is_err());
+
+        let mut ws = Workspace::at(tmp.path())?;
+        let registered = ws.import(crate::import::scan(&ws.root, &ws.root)?, OnConflict::Skip)?;
+        assert_eq!(registered.len(), 2);
+
+        let mut ws = Workspace::at(tmp.path())?;
+        let registered = ws.import(crate::import::scan(&ws.root, &ws.root)?, OnConflict::Rename)?;
+        let names: Vec<_> = registered.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "a-2", "b"]);
+        assert_eq!(ws.project("a-2")?.config.path, Some("c".into()));
+        assert!(ws.project("b")?.editable);
+
+        // importing the same projects again skips them
+        assert!(ws.import(crate::import::scan(&ws.root, &ws.root)?, OnConflict::Fail)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn env_lifecycle_manages_lockfiles() -> Result<()> {
+        let tmp = TempDir::new("workspace")?;
+        Workspace::init(tmp.path())?.save()?;
+
+        let mut ws = Workspace::at(tmp.path())?;
+        ws.add_env("stage", None, &Some("dev".to_string()))?;
+        ws.save()?;
+        assert!(ws.lock_path("stage").exists());
+
+        let mut ws = Workspace::at(tmp.path())?;
+        assert_eq!(ws.config.environments(), vec!["dev", "stage"]);
+        ws.rename_env("stage", "prod")?;
+        ws.save()?;
+        assert!(!ws.lock_path("stage").exists());
+        assert!(ws.lock_path("prod").exists());
+
+        let mut ws = Workspace::at(tmp.path())?;
+        ws.rm_env("prod")?;
+        ws.save()?;
+        assert!(!ws.lock_path("prod").exists());
+        assert_eq!(Workspace::at(tmp.path())?.config.environments(), vec!["dev"]);
+        Ok(())
+    }
 }